@@ -2,11 +2,10 @@
 
 use std::path::Path;
 
-use tracing::debug;
+use anyhow::bail;
+use tracing::{debug, warn};
 
-use crate::build_tools::{
-    cargo, elm, flutter, gradle, mix, npm, BuildTool, BuildToolKind, BuildToolProbe,
-};
+use crate::build_tools::{cargo, elm, flutter, gradle, mix, npm, BuildTool, BuildToolProbe};
 
 /// Used to identify build tools (projects).
 ///
@@ -19,10 +18,9 @@ use crate::build_tools::{
 ///
 /// ```
 /// use makeclean::build_tool_manager::BuildToolManager;
-/// use makeclean::build_tools::BuildToolKind::*;
 ///
 /// let mut build_tool_manager = BuildToolManager::default();
-/// build_tool_manager.filter(&[Rust, Npm]);
+/// build_tool_manager.filter(&["rust", "npm"]).unwrap();
 /// ```
 ///
 /// Alternatively, start with an instance with no build tool probe configured
@@ -78,22 +76,66 @@ impl BuildToolManager {
     /// Filters the current list of probes.
     ///
     /// Any probes that are not related to any of the names given in
-    /// `project_types` are discarded.
-    pub fn filter(&mut self, project_types: &[BuildToolKind]) {
-        let mut probes = Vec::new();
-        std::mem::swap(&mut self.probes, &mut probes);
-
-        self.probes = probes
-            .into_iter()
-            // We keep the probe if it applies to any of the requested types
-            .filter(|probe| {
-                project_types
-                    .iter()
-                    .any(|project_type| probe.applies_to(*project_type))
-            })
-            .collect();
+    /// `project_types` are discarded. Fails if any of the names is not
+    /// recognized by any probe.
+    pub fn filter<S: AsRef<str>>(&mut self, project_types: &[S]) -> anyhow::Result<()> {
+        for project_type in project_types.iter().map(AsRef::as_ref) {
+            if !self
+                .probes
+                .iter()
+                .any(|probe| probe.applies_to(project_type))
+            {
+                bail!(
+                    "Unknown project type {project_type:?}. Known project types: {}",
+                    self.probes
+                        .iter()
+                        .map(|probe| probe.id())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            }
+        }
+
+        // We keep the probe if it applies to any of the requested types
+        self.probes.retain(|probe| {
+            project_types
+                .iter()
+                .any(|project_type| probe.applies_to(project_type.as_ref()))
+        });
 
         debug!("build tools filtered: {:?}", &self.probes);
+        Ok(())
+    }
+
+    /// Removes probes from the current list.
+    ///
+    /// Any probes that are related to any of the names given in `names` are
+    /// discarded.
+    pub fn discard<S: AsRef<str>>(&mut self, names: &[S]) {
+        for name in names.iter().map(AsRef::as_ref) {
+            if !self.probes.iter().any(|probe| probe.applies_to(name)) {
+                warn!("Cannot disable unknown project type {name:?}");
+            }
+        }
+
+        self.probes
+            .retain(|probe| !names.iter().any(|name| probe.applies_to(name.as_ref())));
+
+        debug!("build tools after discarding: {:?}", &self.probes);
+    }
+
+    /// The registered probes, in the order they were registered.
+    pub fn probes(&self) -> &[Box<dyn BuildToolProbe>] {
+        &self.probes
+    }
+
+    /// The names of directories that only ever hold dependencies; see
+    /// [`BuildToolProbe::dependency_dirs`].
+    pub fn dependency_dirs(&self) -> Vec<&str> {
+        self.probes
+            .iter()
+            .flat_map(|probe| probe.dependency_dirs())
+            .collect()
     }
 
     /// Returns all build tools configured in a given directory.
@@ -1,5 +1,8 @@
-use super::{remove_dirs, status_from_dirs, BuildStatus, BuildTool, BuildToolKind, BuildToolProbe};
-use crate::build_tool_manager::BuildToolManager;
+use super::{
+    categorize_dirs, join_dirs, plan_removal, status_from_dirs, BuildStatus, BuildTool,
+    BuildToolProbe, Category, CleanStep, Removal,
+};
+use crate::{build_tool_manager::BuildToolManager, project::scan::ProjectScan};
 
 use displaydoc::Display;
 use serde::Deserialize;
@@ -27,9 +30,16 @@ impl BuildToolProbe for CargoProbe {
         })
     }
 
-    fn applies_to(&self, kind: BuildToolKind) -> bool {
-        use BuildToolKind::*;
-        matches!(kind, Cargo | Rust | Rs)
+    fn id(&self) -> &str {
+        "cargo"
+    }
+
+    fn aliases(&self) -> Vec<&str> {
+        vec!["rust", "rs"]
+    }
+
+    fn ephemeral_dirs(&self) -> Vec<&str> {
+        EPHEMERAL_DIRS.to_vec()
     }
 }
 
@@ -43,20 +53,32 @@ pub struct Cargo {
 static EPHEMERAL_DIRS: &[&str] = &["target"];
 
 impl BuildTool for Cargo {
-    fn clean_project(&mut self, dry_run: bool) -> anyhow::Result<()> {
+    fn clean_plan(&self, _removal: Removal) -> anyhow::Result<Vec<CleanStep>> {
         // `cargo clean` exists, but according to its man page:
         // "With no options, cargo clean will delete the entire target directory.".
         // So removing the target directory directly instead of shelling out has
         // the same effect, and also works in case Cargo is not installed on the
         // system.
 
-        remove_dirs(&self.dir, EPHEMERAL_DIRS, dry_run)
+        plan_removal(&self.dir, EPHEMERAL_DIRS)
+    }
+
+    fn ephemeral_dirs(&self) -> Vec<PathBuf> {
+        join_dirs(&self.dir, EPHEMERAL_DIRS)
+    }
+
+    fn categorized_ephemeral_dirs(&self) -> Vec<(PathBuf, Category)> {
+        categorize_dirs(&self.dir, EPHEMERAL_DIRS, Category::BuildOutputs).collect()
     }
 
     fn status(&self) -> anyhow::Result<BuildStatus> {
         status_from_dirs(&self.dir, EPHEMERAL_DIRS)
     }
 
+    fn status_from_scan(&self, scan: &ProjectScan) -> anyhow::Result<BuildStatus> {
+        Ok(BuildStatus::from_paths(&self.ephemeral_paths(scan)))
+    }
+
     fn project_name(&self) -> Option<anyhow::Result<String>> {
         Some(Ok(self.toml.package.name.clone()))
     }
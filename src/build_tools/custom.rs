@@ -0,0 +1,663 @@
+//! Build tools declared in the configuration file.
+//!
+//! For example, the following declares a Bazel build tool:
+//!
+//! ```toml
+//! [[custom_tools]]
+//! name = "bazel"
+//! markers = ["WORKSPACE", "WORKSPACE.bazel"]
+//! ephemeral_dirs = ["bazel-*"]
+//! category = "build-outputs"
+//!
+//! [custom_tools.project_name]
+//! file = "MODULE.bazel"
+//! regex = 'module\(\s*name\s*=\s*"([^"]+)"'
+//! ```
+
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, format_err, Context};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use super::{
+    measure_paths, BuildStatus, BuildTool, BuildToolProbe, Category, CleanStep, EphemeralPath,
+    Removal,
+};
+use crate::{build_tool_manager::BuildToolManager, fs::Hardlinks, project::scan::ProjectScan};
+
+/// Registers a probe for each of the given declarations.
+pub fn register(
+    manager: &mut BuildToolManager,
+    custom_tools: &[CustomToolConfig],
+) -> anyhow::Result<()> {
+    for config in custom_tools {
+        let probe = CustomProbe::try_from(config)
+            .with_context(|| format!("Invalid custom build tool {:?}", config.name))?;
+        manager.register(Box::new(probe));
+    }
+    Ok(())
+}
+
+/// The declaration of a build tool in the configuration file.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CustomToolConfig {
+    /// The name of the build tool, which can also be used with `--type`.
+    pub name: String,
+    /// Alternative names that can be used with `--type`.
+    pub aliases: Vec<String>,
+    /// A directory is considered a project if it contains at least one file
+    /// or directory matching any of these glob patterns.
+    pub markers: Vec<String>,
+    /// Files or directories that must be present as well (glob patterns).
+    pub requires: Vec<String>,
+    /// Files or directories that must not be present (glob patterns).
+    pub forbids: Vec<String>,
+    /// Directories that are removed when cleaning the project.
+    ///
+    /// Patterns without a slash match at any depth; patterns with a slash are
+    /// relative to the project directory. Nested projects, i.e. Git
+    /// repositories and directories containing any of the `markers`, aren't
+    /// searched. Symbolic links that match are removed without following
+    /// them.
+    pub ephemeral_dirs: Vec<String>,
+    /// Files that are removed when cleaning the project, with the same
+    /// semantics as `ephemeral_dirs`.
+    pub ephemeral_files: Vec<String>,
+    /// What kind of content the ephemeral directories and files hold, e.g.,
+    /// "dependencies" for a Python virtualenv. Without a category, they're
+    /// only removed when all categories are cleaned.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub category: Option<Category>,
+    /// How to find out the project's name. Defaults to the directory name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub project_name: Option<ProjectNameRule>,
+}
+
+/// Extracts the project name from a file in the project directory.
+///
+/// Either `key` or `regex` must be set.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProjectNameRule {
+    /// The file that contains the name, relative to the project directory.
+    pub file: PathBuf,
+    /// The file format used with `key`. Derived from the file extension if
+    /// not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<FileFormat>,
+    /// A dot-separated path to the name, e.g. "package.name".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// A regular expression, the first capture group of which is the name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+}
+
+/// Structured file formats that `ProjectNameRule::key` can be used with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileFormat {
+    Toml,
+    Json,
+    Yaml,
+}
+
+#[derive(Debug)]
+pub struct CustomProbe {
+    name: String,
+    aliases: Vec<String>,
+    ephemeral_dirs: Vec<String>,
+    markers: GlobSet,
+    requires: Vec<GlobSet>,
+    forbids: GlobSet,
+    ephemeral: EphemeralPatterns,
+    category: Option<Category>,
+    project_name: Option<NameExtractor>,
+}
+
+impl TryFrom<&CustomToolConfig> for CustomProbe {
+    type Error = anyhow::Error;
+
+    fn try_from(config: &CustomToolConfig) -> Result<Self, Self::Error> {
+        if config.name.trim().is_empty() {
+            bail!("The name must not be empty");
+        }
+        if config.markers.is_empty() {
+            bail!("At least one marker is required");
+        }
+
+        let requires = config
+            .requires
+            .iter()
+            .map(|pattern| globset(std::slice::from_ref(pattern)))
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            name: config.name.clone(),
+            aliases: config.aliases.clone(),
+            ephemeral_dirs: config.ephemeral_dirs.clone(),
+            markers: globset(&config.markers)?,
+            requires,
+            forbids: globset(&config.forbids)?,
+            ephemeral: EphemeralPatterns::new(&config.ephemeral_dirs, &config.ephemeral_files)?,
+            category: config.category,
+            project_name: config
+                .project_name
+                .as_ref()
+                .map(NameExtractor::try_from)
+                .transpose()?,
+        })
+    }
+}
+
+impl BuildToolProbe for CustomProbe {
+    fn probe(&self, dir: &Path) -> Option<Box<dyn BuildTool>> {
+        let file_names: Vec<_> = fs::read_dir(dir)
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name())
+            .collect();
+        let any_match = |set: &GlobSet| file_names.iter().any(|name| set.is_match(name));
+
+        let is_project = any_match(&self.markers)
+            && self.requires.iter().all(any_match)
+            && !any_match(&self.forbids);
+
+        if is_project {
+            Some(Box::new(Custom {
+                name: self.name.clone(),
+                dir: dir.to_owned(),
+                ephemeral_paths: self.ephemeral.find(dir, &self.markers),
+                category: self.category,
+                project_name: self.project_name.clone(),
+            }))
+        } else {
+            None
+        }
+    }
+
+    fn id(&self) -> &str {
+        &self.name
+    }
+
+    fn aliases(&self) -> Vec<&str> {
+        self.aliases.iter().map(String::as_str).collect()
+    }
+
+    fn ephemeral_dirs(&self) -> Vec<&str> {
+        self.ephemeral_dirs.iter().map(String::as_str).collect()
+    }
+}
+
+#[derive(Debug)]
+pub struct Custom {
+    name: String,
+    dir: PathBuf,
+    /// The ephemeral directories and files, found once when the project is
+    /// recognized.
+    ephemeral_paths: Vec<PathBuf>,
+    category: Option<Category>,
+    project_name: Option<NameExtractor>,
+}
+
+impl fmt::Display for Custom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl BuildTool for Custom {
+    fn clean_plan(&self, _removal: Removal) -> anyhow::Result<Vec<CleanStep>> {
+        Ok(self
+            .ephemeral_paths
+            .iter()
+            .filter(|path| fs::symlink_metadata(path).is_ok())
+            .cloned()
+            .map(CleanStep::remove)
+            .collect())
+    }
+
+    fn status(&self) -> anyhow::Result<BuildStatus> {
+        let scan = ProjectScan::of_ephemeral(&self.ephemeral_dirs(), &Hardlinks::default());
+        self.status_from_scan(&scan)
+    }
+
+    fn status_from_scan(&self, scan: &ProjectScan) -> anyhow::Result<BuildStatus> {
+        Ok(BuildStatus::from_paths(&self.ephemeral_paths(scan)))
+    }
+
+    fn ephemeral_dirs(&self) -> Vec<PathBuf> {
+        self.ephemeral_paths.clone()
+    }
+
+    fn ephemeral_paths(&self, scan: &ProjectScan) -> Vec<EphemeralPath> {
+        measure_paths(self.ephemeral_paths.clone(), self.category, scan)
+    }
+
+    fn categorized_ephemeral_dirs(&self) -> Vec<(PathBuf, Category)> {
+        match self.category {
+            Some(category) => self
+                .ephemeral_paths
+                .iter()
+                .map(|path| (path.clone(), category))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn project_name(&self) -> Option<anyhow::Result<String>> {
+        self.project_name
+            .as_ref()
+            .map(|extractor| extractor.extract(&self.dir))
+    }
+}
+
+/// Directories of version control systems, which are never searched.
+const VCS_DIRS: &[&str] = &[".git", ".hg", ".svn"];
+
+/// Matches ephemeral directories and files within a project.
+#[derive(Debug, Clone)]
+struct EphemeralPatterns {
+    dirs: PatternSet,
+    files: PatternSet,
+}
+
+impl EphemeralPatterns {
+    fn new(dirs: &[String], files: &[String]) -> anyhow::Result<Self> {
+        Ok(Self {
+            dirs: PatternSet::new(dirs)?,
+            files: PatternSet::new(files)?,
+        })
+    }
+
+    /// Returns the paths of all ephemeral directories and files in the
+    /// project, without descending into ephemeral directories. Symbolic links
+    /// match the directory patterns, too.
+    ///
+    /// Neither version control directories nor nested projects are searched,
+    /// so files of other projects are never matched. A nested project is a
+    /// directory that is a Git repository, or that contains any of the
+    /// `markers` of the build tool.
+    fn find(&self, project_dir: &Path, markers: &GlobSet) -> Vec<PathBuf> {
+        if self.dirs.is_empty() && self.files.is_empty() {
+            return Vec::new();
+        }
+
+        let is_nested_project = |dir: &Path| {
+            dir.join(".git").exists()
+                || fs::read_dir(dir).map_or(false, |entries| {
+                    entries
+                        .filter_map(|entry| entry.ok())
+                        .any(|entry| markers.is_match(entry.file_name()))
+                })
+        };
+
+        let mut paths = Vec::new();
+        let mut walker = WalkDir::new(project_dir).min_depth(1).into_iter();
+        while let Some(entry) = walker.next() {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(_) => continue,
+            };
+            let relative = entry
+                .path()
+                .strip_prefix(project_dir)
+                .expect("walked path is within the project dir");
+            let file_type = entry.file_type();
+            if file_type.is_dir()
+                && (VCS_DIRS.iter().any(|name| entry.file_name() == *name)
+                    || is_nested_project(entry.path()))
+            {
+                walker.skip_current_dir();
+            } else if file_type.is_dir() && self.dirs.is_match(relative) {
+                paths.push(entry.path().to_owned());
+                walker.skip_current_dir();
+            } else if entry.path_is_symlink() && self.dirs.is_match(relative) {
+                // Links aren't followed, so cleaning only removes the link,
+                // e.g., Bazel's `bazel-out`
+                paths.push(entry.path().to_owned());
+            } else if !file_type.is_dir() && self.files.is_match(relative) {
+                paths.push(entry.path().to_owned());
+            }
+        }
+        paths
+    }
+}
+
+/// Glob patterns following `.gitignore` conventions: patterns without a slash
+/// match the file name at any depth, all other patterns match the path
+/// relative to the project directory.
+#[derive(Debug, Clone)]
+struct PatternSet {
+    names: GlobSet,
+    paths: GlobSet,
+}
+
+impl PatternSet {
+    fn new(patterns: &[String]) -> anyhow::Result<Self> {
+        let (paths, names): (Vec<String>, Vec<String>) = patterns
+            .iter()
+            .map(|pattern| pattern.trim_end_matches('/').to_owned())
+            .partition(|pattern| pattern.contains('/'));
+        let paths: Vec<String> = paths
+            .iter()
+            .map(|pattern| pattern.trim_start_matches('/').to_owned())
+            .collect();
+        Ok(Self {
+            names: globset(&names)?,
+            paths: globset(&paths)?,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.paths.is_empty()
+    }
+
+    fn is_match(&self, relative: &Path) -> bool {
+        self.paths.is_match(relative)
+            || relative
+                .file_name()
+                .map_or(false, |name| self.names.is_match(name))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum NameExtractor {
+    Key {
+        file: PathBuf,
+        format: FileFormat,
+        key: Vec<String>,
+    },
+    Regex {
+        file: PathBuf,
+        regex: Regex,
+    },
+}
+
+impl TryFrom<&ProjectNameRule> for NameExtractor {
+    type Error = anyhow::Error;
+
+    fn try_from(rule: &ProjectNameRule) -> Result<Self, Self::Error> {
+        let file = rule.file.clone();
+        match (&rule.key, &rule.regex) {
+            (Some(key), None) => {
+                let format = match rule.format {
+                    Some(format) => format,
+                    None => match file.extension().and_then(|ext| ext.to_str()) {
+                        Some("toml") => FileFormat::Toml,
+                        Some("json") => FileFormat::Json,
+                        Some("yaml" | "yml") => FileFormat::Yaml,
+                        _ => bail!(
+                            "Cannot derive the format of {}, please set project_name.format",
+                            file.display()
+                        ),
+                    },
+                };
+                let key = key.split('.').map(str::to_owned).collect();
+                Ok(Self::Key { file, format, key })
+            }
+            (None, Some(regex)) => {
+                let regex = Regex::new(regex)?;
+                if regex.captures_len() < 2 {
+                    bail!("The regex {regex:?} has no capture group");
+                }
+                Ok(Self::Regex { file, regex })
+            }
+            _ => bail!("Exactly one of project_name.key and project_name.regex must be set"),
+        }
+    }
+}
+
+impl NameExtractor {
+    fn extract(&self, project_dir: &Path) -> anyhow::Result<String> {
+        match self {
+            Self::Key { file, format, key } => {
+                let content = fs::read_to_string(project_dir.join(file))?;
+                let mut value: serde_json::Value = match format {
+                    FileFormat::Toml => {
+                        serde_json::to_value(toml::from_str::<toml::Value>(&content)?)?
+                    }
+                    FileFormat::Json => serde_json::from_str(&content)?,
+                    FileFormat::Yaml => {
+                        serde_json::to_value(serde_yaml::from_str::<serde_yaml::Value>(&content)?)?
+                    }
+                };
+                for segment in key {
+                    value = value
+                        .get_mut(segment)
+                        .map(serde_json::Value::take)
+                        .ok_or_else(|| {
+                            format_err!("{} has no key {:?}", file.display(), key.join("."))
+                        })?;
+                }
+                match value {
+                    serde_json::Value::String(name) => Ok(name),
+                    other => bail!("Expected a string at {:?}, got {other}", key.join(".")),
+                }
+            }
+            Self::Regex { file, regex } => {
+                let content = fs::read_to_string(project_dir.join(file))?;
+                regex
+                    .captures(&content)
+                    .and_then(|captures| captures.get(1))
+                    .map(|name| name.as_str().to_owned())
+                    .ok_or_else(|| format_err!("{regex:?} does not match {}", file.display()))
+            }
+        }
+    }
+}
+
+fn globset(patterns: &[String]) -> anyhow::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern).with_context(|| format!("Invalid pattern {pattern:?}"))?;
+        builder.add(glob);
+    }
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod test {
+    use assert_fs::{
+        fixture::{FileWriteStr, PathChild},
+        TempDir,
+    };
+    use claim::assert_matches;
+
+    use super::*;
+    use crate::build_tools::{Removal, Remover};
+
+    fn probe(toml: &str) -> CustomProbe {
+        let config: CustomToolConfig = toml::from_str(toml).unwrap();
+        CustomProbe::try_from(&config).unwrap()
+    }
+
+    #[test]
+    fn recognizes_projects_by_markers_and_siblings() {
+        let probe = probe(
+            r#"
+            name = "stack"
+            markers = ["*.cabal"]
+            requires = ["stack.yaml"]
+            forbids = ["cabal.project"]
+            "#,
+        );
+        let root = TempDir::new().unwrap();
+
+        root.child("ok").child("foo.cabal").write_str("").unwrap();
+        root.child("ok").child("stack.yaml").write_str("").unwrap();
+        assert!(probe.probe(&root.child("ok")).is_some());
+
+        root.child("no-stack")
+            .child("foo.cabal")
+            .write_str("")
+            .unwrap();
+        assert!(probe.probe(&root.child("no-stack")).is_none());
+
+        root.child("forbidden")
+            .child("foo.cabal")
+            .write_str("")
+            .unwrap();
+        root.child("forbidden")
+            .child("stack.yaml")
+            .write_str("")
+            .unwrap();
+        root.child("forbidden")
+            .child("cabal.project")
+            .write_str("")
+            .unwrap();
+        assert!(probe.probe(&root.child("forbidden")).is_none());
+    }
+
+    #[test]
+    fn cleans_ephemeral_dirs_and_files() {
+        let probe = probe(
+            r#"
+            name = "python"
+            markers = ["setup.py"]
+            ephemeral_dirs = ["__pycache__", "build/"]
+            ephemeral_files = ["*.pyc"]
+            "#,
+        );
+        let root = TempDir::new().unwrap();
+        root.child("setup.py").write_str("").unwrap();
+        root.child("src/main.py").write_str("code").unwrap();
+        root.child("src/main.pyc").write_str("bytecode").unwrap();
+        root.child("src/__pycache__/main.cpython-310.pyc")
+            .write_str("bytecode")
+            .unwrap();
+        root.child("build/lib/main.py").write_str("code").unwrap();
+
+        let tool = probe.probe(&root).unwrap();
+        assert_matches!(tool.status().unwrap(), BuildStatus::Built { freeable_bytes, .. } if freeable_bytes > 0);
+
+        let mut ephemeral_dirs = tool.ephemeral_dirs();
+        ephemeral_dirs.sort();
+        assert_eq!(
+            ephemeral_dirs,
+            vec![
+                root.child("build").to_path_buf(),
+                root.child("src/__pycache__").to_path_buf(),
+                root.child("src/main.pyc").to_path_buf(),
+            ]
+        );
+
+        let remover = Remover::new(Removal::Delete);
+        for step in tool.clean_plan(Removal::Delete).unwrap() {
+            remover.execute(&step).unwrap();
+        }
+
+        assert!(root.child("setup.py").exists());
+        assert!(root.child("src/main.py").exists());
+        assert!(!root.child("src/main.pyc").exists());
+        assert!(!root.child("src/__pycache__").exists());
+        assert!(!root.child("build").exists());
+        assert_matches!(tool.status().unwrap(), BuildStatus::Clean);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn finds_symlinked_ephemeral_dirs_and_only_unlinks_them() {
+        let probe = probe(
+            r#"
+            name = "bazel"
+            markers = ["WORKSPACE"]
+            ephemeral_dirs = ["bazel-*"]
+            "#,
+        );
+        let root = TempDir::new().unwrap();
+        let output_base = TempDir::new().unwrap();
+        output_base.child("out/artifact").write_str("").unwrap();
+        root.child("WORKSPACE").write_str("").unwrap();
+        std::os::unix::fs::symlink(output_base.child("out"), root.child("bazel-out")).unwrap();
+
+        let tool = probe.probe(&root).unwrap();
+        assert_eq!(
+            tool.ephemeral_dirs(),
+            vec![root.child("bazel-out").to_path_buf()]
+        );
+
+        let remover = Remover::new(Removal::Delete);
+        for step in tool.clean_plan(Removal::Delete).unwrap() {
+            remover.execute(&step).unwrap();
+        }
+        assert!(fs::symlink_metadata(root.child("bazel-out")).is_err());
+        assert!(output_base.child("out/artifact").exists());
+    }
+
+    #[test]
+    fn doesnt_search_nested_projects() {
+        let probe = probe(
+            r#"
+            name = "python"
+            markers = ["setup.py"]
+            ephemeral_dirs = ["__pycache__"]
+            ephemeral_files = ["*.pyc"]
+            "#,
+        );
+        let root = TempDir::new().unwrap();
+        root.child("setup.py").write_str("").unwrap();
+        root.child("__pycache__/main.pyc").write_str("").unwrap();
+        root.child("nested/setup.py").write_str("").unwrap();
+        root.child("nested/__pycache__/main.pyc")
+            .write_str("")
+            .unwrap();
+        root.child("repo/.git/HEAD").write_str("").unwrap();
+        root.child("repo/main.pyc").write_str("").unwrap();
+        root.child(".git/objects/foo.pyc").write_str("").unwrap();
+
+        let tool = probe.probe(&root).unwrap();
+        assert_eq!(
+            tool.ephemeral_dirs(),
+            vec![root.child("__pycache__").to_path_buf()]
+        );
+    }
+
+    #[test]
+    fn extracts_the_project_name_by_key_or_regex() {
+        let root = TempDir::new().unwrap();
+        root.child("package.yaml")
+            .write_str("package:\n  name: by-key\n")
+            .unwrap();
+        root.child("build.txt")
+            .write_str("project = \"by-regex\"\n")
+            .unwrap();
+
+        let by_key = probe(
+            r#"
+            name = "by-key"
+            markers = ["package.yaml"]
+            project_name = { file = "package.yaml", key = "package.name" }
+            "#,
+        );
+        let name = by_key
+            .probe(&root)
+            .unwrap()
+            .project_name()
+            .unwrap()
+            .unwrap();
+        assert_eq!(name, "by-key");
+
+        let by_regex = probe(
+            r#"
+            name = "by-regex"
+            markers = ["build.txt"]
+            project_name = { file = "build.txt", regex = 'project = "(.+)"' }
+            "#,
+        );
+        let name = by_regex
+            .probe(&root)
+            .unwrap()
+            .project_name()
+            .unwrap()
+            .unwrap();
+        assert_eq!(name, "by-regex");
+    }
+}
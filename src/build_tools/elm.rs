@@ -1,7 +1,10 @@
 use displaydoc::Display;
 
-use super::{remove_dirs, status_from_dirs, BuildStatus, BuildTool, BuildToolKind, BuildToolProbe};
-use crate::build_tool_manager::BuildToolManager;
+use super::{
+    categorize_dirs, join_dirs, plan_removal, status_from_dirs, BuildStatus, BuildTool,
+    BuildToolProbe, Category, CleanStep, Removal,
+};
+use crate::{build_tool_manager::BuildToolManager, project::scan::ProjectScan};
 use std::path::{Path, PathBuf};
 
 pub fn register(manager: &mut BuildToolManager) {
@@ -23,8 +26,12 @@ impl BuildToolProbe for ElmProbe {
         }
     }
 
-    fn applies_to(&self, kind: BuildToolKind) -> bool {
-        kind == BuildToolKind::Elm
+    fn id(&self) -> &str {
+        "elm"
+    }
+
+    fn ephemeral_dirs(&self) -> Vec<&str> {
+        EPHEMERAL_DIRS.to_vec()
     }
 }
 
@@ -37,11 +44,23 @@ pub struct Elm {
 static EPHEMERAL_DIRS: &[&str] = &["elm-stuff"];
 
 impl BuildTool for Elm {
-    fn clean_project(&mut self, dry_run: bool) -> anyhow::Result<()> {
-        remove_dirs(&self.dir, EPHEMERAL_DIRS, dry_run)
+    fn clean_plan(&self, _removal: Removal) -> anyhow::Result<Vec<CleanStep>> {
+        plan_removal(&self.dir, EPHEMERAL_DIRS)
+    }
+
+    fn ephemeral_dirs(&self) -> Vec<PathBuf> {
+        join_dirs(&self.dir, EPHEMERAL_DIRS)
+    }
+
+    fn categorized_ephemeral_dirs(&self) -> Vec<(PathBuf, Category)> {
+        categorize_dirs(&self.dir, EPHEMERAL_DIRS, Category::BuildOutputs).collect()
     }
 
     fn status(&self) -> anyhow::Result<BuildStatus> {
         status_from_dirs(&self.dir, EPHEMERAL_DIRS)
     }
+
+    fn status_from_scan(&self, scan: &ProjectScan) -> anyhow::Result<BuildStatus> {
+        Ok(BuildStatus::from_paths(&self.ephemeral_paths(scan)))
+    }
 }
@@ -0,0 +1,463 @@
+//! Build tools recognized by external programs ("probe plugins").
+//!
+//! A probe plugin is an executable that is either found on `PATH` with a name
+//! starting with `makeclean-probe-`, or listed in the configuration file under
+//! `external_probes`. For every directory that is searched, the plugin is
+//! executed with a JSON request written to its stdin:
+//!
+//! ```json
+//! {"version":1,"dir":"/home/user/code/some-project"}
+//! ```
+//!
+//! If the directory is not a project it recognizes, the plugin prints nothing
+//! (or `null`). Otherwise, it prints a description of the project:
+//!
+//! ```json
+//! {
+//!   "tool": "Acme",
+//!   "project_name": "some-project",
+//!   "ephemeral_paths": ["out", ".acme-cache"],
+//!   "freeable_bytes": 1234,
+//!   "clean_command": ["acme", "clean"]
+//! }
+//! ```
+//!
+//! Only `tool` is required. Ephemeral paths are relative to the project
+//! directory and must not point outside of it. The size of the project is
+//! computed from the ephemeral paths; `freeable_bytes` is only used if none of
+//! them exist, e.g., because the plugin doesn't name any. If `clean_command`
+//! is set, cleaning the project runs that command in the project directory;
+//! otherwise, the ephemeral paths are removed.
+//!
+//! As the plugin is executed for every directory, it should answer quickly. If
+//! it doesn't respond within five seconds, it's killed, and the directory is
+//! treated as if the plugin didn't recognize it.
+//!
+//! The plugin name, i.e., the executable's file name without the
+//! `makeclean-probe-` prefix, can be used with `--type`.
+
+use std::{
+    collections::HashSet,
+    env, fmt, fs,
+    io::{Read, Write},
+    path::{Component, Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use super::{
+    measure_paths, reason_to_keep, BuildStatus, BuildTool, BuildToolProbe, CleanStep,
+    EphemeralPath, Removal,
+};
+use crate::{build_tool_manager::BuildToolManager, fs::Hardlinks, project::scan::ProjectScan};
+
+/// The file name prefix of probe plugins that are found on `PATH`.
+pub const PLUGIN_PREFIX: &str = "makeclean-probe-";
+
+/// The version of the protocol, sent with every request.
+const PROTOCOL_VERSION: u32 = 1;
+
+/// How long a plugin may take to respond before it's killed.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Registers the given probe plugins, as well as any plugins found on `PATH`.
+pub fn register(manager: &mut BuildToolManager, executables: &[PathBuf]) {
+    let mut names = HashSet::new();
+    for executable in executables.iter().cloned().chain(plugins_on_path()) {
+        let probe = ExternalProbe::new(executable);
+        // Like with shell commands, the first plugin with a given name wins
+        if names.insert(probe.name.clone()) {
+            debug!("registering probe plugin {:?}", probe.executable);
+            manager.register(Box::new(probe));
+        }
+    }
+}
+
+fn plugins_on_path() -> Vec<PathBuf> {
+    let path = match env::var_os("PATH") {
+        Some(path) => path,
+        None => return Vec::new(),
+    };
+
+    let mut plugins: Vec<PathBuf> = Vec::new();
+    for dir in env::split_paths(&path) {
+        let mut in_dir: Vec<PathBuf> = match fs::read_dir(&dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| {
+                    entry
+                        .file_name()
+                        .to_string_lossy()
+                        .starts_with(PLUGIN_PREFIX)
+                })
+                .map(|entry| entry.path())
+                .filter(|path| is_executable(path))
+                .collect(),
+            Err(_) => continue,
+        };
+        // read_dir has no defined order
+        in_dir.sort();
+        plugins.extend(in_dir);
+    }
+    plugins
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[derive(Debug, Serialize)]
+struct ProbeRequest<'a> {
+    version: u32,
+    dir: &'a Path,
+}
+
+/// What a probe plugin reports about a project.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProbeResponse {
+    pub tool: String,
+    #[serde(default)]
+    pub project_name: Option<String>,
+    #[serde(default)]
+    pub ephemeral_paths: Vec<PathBuf>,
+    #[serde(default)]
+    pub freeable_bytes: Option<u64>,
+    #[serde(default)]
+    pub clean_command: Option<Vec<String>>,
+}
+
+#[derive(Debug)]
+pub struct ExternalProbe {
+    name: String,
+    executable: PathBuf,
+}
+
+impl ExternalProbe {
+    pub fn new(executable: PathBuf) -> Self {
+        let file_name = executable
+            .file_stem()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let name = file_name
+            .strip_prefix(PLUGIN_PREFIX)
+            .unwrap_or(&file_name)
+            .to_owned();
+        Self { name, executable }
+    }
+
+    fn request(&self, dir: &Path) -> anyhow::Result<Option<ProbeResponse>> {
+        let mut child = Command::new(&self.executable)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .with_context(|| format!("Failed to execute {:?}", self.executable))?;
+
+        let request = ProbeRequest {
+            version: PROTOCOL_VERSION,
+            dir,
+        };
+        {
+            let mut stdin = child.stdin.take().expect("stdin is piped");
+            let mut line = serde_json::to_vec(&request)?;
+            line.push(b'\n');
+            // The plugin may exit without reading its input, so a broken pipe is fine
+            let _ = stdin.write_all(&line);
+        }
+
+        // The output is read while waiting, so the plugin doesn't block on a
+        // full pipe
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let reader = thread::spawn(move || {
+            let mut output = Vec::new();
+            stdout.read_to_end(&mut output).map(|_| output)
+        });
+        let deadline = Instant::now() + RESPONSE_TIMEOUT;
+        let mut poll_interval = Duration::from_millis(1);
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                bail!(
+                    "{:?} didn't respond within {} seconds and was killed",
+                    self.executable,
+                    RESPONSE_TIMEOUT.as_secs()
+                );
+            }
+            thread::sleep(poll_interval);
+            poll_interval = (poll_interval * 2).min(Duration::from_millis(50));
+        };
+        if !status.success() {
+            bail!("Unexpected exit code {} for {:?}", status, self.executable);
+        }
+
+        let stdout = reader.join().expect("reading doesn't panic")?;
+        let stdout = String::from_utf8(stdout)?;
+        if stdout.trim().is_empty() {
+            return Ok(None);
+        }
+        let response: Option<ProbeResponse> = serde_json::from_str(&stdout)
+            .with_context(|| format!("Unexpected response from {:?}", self.executable))?;
+        Ok(response)
+    }
+}
+
+impl BuildToolProbe for ExternalProbe {
+    fn probe(&self, dir: &Path) -> Option<Box<dyn BuildTool>> {
+        match self.request(dir) {
+            Ok(Some(response)) => match External::new(dir, response) {
+                Ok(tool) => Some(Box::new(tool)),
+                Err(e) => {
+                    warn!("Ignoring response of probe plugin {}: {e:#}", self.name);
+                    None
+                }
+            },
+            Ok(None) => None,
+            Err(e) => {
+                warn!(
+                    "Probe plugin {} failed for {}: {e:#}",
+                    self.name,
+                    dir.display()
+                );
+                None
+            }
+        }
+    }
+
+    fn id(&self) -> &str {
+        &self.name
+    }
+}
+
+#[derive(Debug)]
+pub struct External {
+    dir: PathBuf,
+    response: ProbeResponse,
+}
+
+impl External {
+    fn new(dir: &Path, mut response: ProbeResponse) -> anyhow::Result<Self> {
+        for path in response.ephemeral_paths.iter_mut() {
+            let relative = path.strip_prefix(dir).unwrap_or(path);
+            let is_within_project = relative
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)));
+            if !is_within_project || relative.as_os_str().is_empty() {
+                bail!(
+                    "Ephemeral path {} is not within the project directory",
+                    path.display()
+                );
+            }
+            *path = dir.join(relative);
+        }
+        Ok(Self {
+            dir: dir.to_owned(),
+            response,
+        })
+    }
+}
+
+impl fmt::Display for External {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.response.tool)
+    }
+}
+
+impl BuildTool for External {
+    fn clean_plan(&self, removal: Removal) -> anyhow::Result<Vec<CleanStep>> {
+        let existing_paths: Vec<PathBuf> = self
+            .response
+            .ephemeral_paths
+            .iter()
+            .filter(|path| path.exists())
+            .cloned()
+            .collect();
+
+        // The clean command can neither move anything to the trash nor keep
+        // what must be kept, so the ephemeral paths are removed directly
+        // instead in those cases
+        let must_remove_directly = removal.is_restorable()
+            || existing_paths
+                .iter()
+                .any(|path| reason_to_keep(path).is_some());
+        if let Some((program, args)) = self
+            .response
+            .clean_command
+            .as_ref()
+            .and_then(|command| command.split_first())
+            .filter(|_| !must_remove_directly)
+        {
+            return Ok(vec![CleanStep::run(
+                program,
+                args,
+                &self.dir,
+                existing_paths,
+            )]);
+        }
+
+        Ok(existing_paths.into_iter().map(CleanStep::remove).collect())
+    }
+
+    fn status(&self) -> anyhow::Result<BuildStatus> {
+        let scan = ProjectScan::of_ephemeral(&self.ephemeral_dirs(), &Hardlinks::default());
+        self.status_from_scan(&scan)
+    }
+
+    fn status_from_scan(&self, scan: &ProjectScan) -> anyhow::Result<BuildStatus> {
+        let paths = self.ephemeral_paths(scan);
+        let status = match self.response.freeable_bytes {
+            // The plugin can't tell apparent and allocated sizes apart, so its
+            // size is only used if there's nothing to measure
+            Some(0) if paths.is_empty() => BuildStatus::Clean,
+            Some(freeable_bytes) if paths.is_empty() => BuildStatus::Built {
+                freeable_bytes,
+                apparent_bytes: freeable_bytes,
+            },
+            _ => BuildStatus::from_paths(&paths),
+        };
+        Ok(status)
+    }
+
+    fn ephemeral_dirs(&self) -> Vec<PathBuf> {
+        self.response.ephemeral_paths.clone()
+    }
+
+    fn ephemeral_paths(&self, scan: &ProjectScan) -> Vec<EphemeralPath> {
+        measure_paths(self.response.ephemeral_paths.clone(), None, scan)
+    }
+
+    fn project_name(&self) -> Option<anyhow::Result<String>> {
+        self.response.project_name.clone().map(Ok)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use std::os::unix::fs::PermissionsExt;
+
+    use assert_fs::{
+        fixture::{FileWriteStr, PathChild},
+        TempDir,
+    };
+    use claim::assert_matches;
+
+    use super::*;
+    use crate::build_tools::{Removal, Remover};
+
+    fn stub_plugin(dir: &TempDir, script: &str) -> PathBuf {
+        let path = dir.child("makeclean-probe-acme");
+        path.write_str(script).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_path_buf()
+    }
+
+    #[test]
+    fn recognizes_and_cleans_projects_reported_by_the_plugin() {
+        let plugin_dir = TempDir::new().unwrap();
+        let plugin = stub_plugin(
+            &plugin_dir,
+            r#"#!/bin/sh
+dir=$(sed -n 's/.*"dir":"\([^"]*\)".*/\1/p')
+if [ -f "$dir/acme.build" ]; then
+  echo '{"tool": "Acme", "project_name": "acme-project", "ephemeral_paths": ["out"]}'
+fi
+"#,
+        );
+        let probe = ExternalProbe::new(plugin);
+        assert!(probe.applies_to("acme"));
+
+        let root = TempDir::new().unwrap();
+        assert!(probe.probe(root.path()).is_none());
+
+        root.child("acme.build").write_str("").unwrap();
+        root.child("out").child("artifact").write_str("1").unwrap();
+        let tool = probe.probe(root.path()).unwrap();
+        assert_eq!(tool.to_string(), "Acme");
+        assert_eq!(tool.project_name().unwrap().unwrap(), "acme-project");
+        assert_matches!(
+            tool.status().unwrap(),
+            BuildStatus::Built {
+                apparent_bytes: 1,
+                ..
+            }
+        );
+
+        let remover = Remover::new(Removal::Delete);
+        for step in tool.clean_plan(Removal::Delete).unwrap() {
+            remover.execute(&step).unwrap();
+        }
+        assert!(!root.child("out").exists());
+        assert!(root.child("acme.build").exists());
+    }
+
+    #[test]
+    fn the_size_reported_by_the_plugin_is_only_used_if_there_is_nothing_to_measure() {
+        let plugin_dir = TempDir::new().unwrap();
+        let plugin = stub_plugin(
+            &plugin_dir,
+            r#"#!/bin/sh
+echo '{"tool": "Acme", "ephemeral_paths": ["out"], "freeable_bytes": 1000}'
+"#,
+        );
+        let probe = ExternalProbe::new(plugin);
+        let root = TempDir::new().unwrap();
+
+        let tool = probe.probe(root.path()).unwrap();
+        assert_matches!(
+            tool.status().unwrap(),
+            BuildStatus::Built {
+                freeable_bytes: 1000,
+                apparent_bytes: 1000,
+            }
+        );
+
+        root.child("out").child("artifact").write_str("1").unwrap();
+        let tool = probe.probe(root.path()).unwrap();
+        let scan = ProjectScan::new(
+            root.path(),
+            &tool.ephemeral_dirs(),
+            &crate::fs::Hardlinks::default(),
+        );
+        assert_eq!(scan.ephemeral.len(), 1);
+        assert_matches!(
+            tool.status_from_scan(&scan).unwrap(),
+            BuildStatus::Built {
+                apparent_bytes: 1,
+                ..
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_ephemeral_paths_outside_the_project() {
+        let plugin_dir = TempDir::new().unwrap();
+        let plugin = stub_plugin(
+            &plugin_dir,
+            r#"#!/bin/sh
+echo '{"tool": "Acme", "ephemeral_paths": ["../sibling"]}'
+"#,
+        );
+        let probe = ExternalProbe::new(plugin);
+
+        let root = TempDir::new().unwrap();
+        assert!(probe.probe(root.path()).is_none());
+    }
+}
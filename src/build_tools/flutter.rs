@@ -1,8 +1,11 @@
 use displaydoc::Display;
 use serde::Deserialize;
 
-use super::{remove_dirs, status_from_dirs, BuildStatus, BuildTool, BuildToolKind, BuildToolProbe};
-use crate::build_tool_manager::BuildToolManager;
+use super::{
+    is_real_dir, join_dirs, plan_removal, status_from_dirs, BuildStatus, BuildTool, BuildToolProbe,
+    Category, CleanStep, Removal, Reproducibility,
+};
+use crate::{build_tool_manager::BuildToolManager, project::scan::ProjectScan};
 use std::{
     fs,
     path::{Path, PathBuf},
@@ -27,8 +30,12 @@ impl BuildToolProbe for FlutterProbe {
         })
     }
 
-    fn applies_to(&self, kind: BuildToolKind) -> bool {
-        kind == BuildToolKind::Flutter
+    fn id(&self) -> &str {
+        "flutter"
+    }
+
+    fn ephemeral_dirs(&self) -> Vec<&str> {
+        EPHEMERAL_DIRS.to_vec()
     }
 }
 
@@ -41,19 +48,47 @@ pub struct Flutter {
 
 static EPHEMERAL_DIRS: &[&str] = &["build", ".dart_tool"];
 
+static CATEGORIES: &[(&str, Category)] = &[
+    ("build", Category::BuildOutputs),
+    (".dart_tool", Category::Dependencies),
+];
+
 impl BuildTool for Flutter {
-    fn clean_project(&mut self, dry_run: bool) -> anyhow::Result<()> {
+    fn clean_plan(&self, _removal: Removal) -> anyhow::Result<Vec<CleanStep>> {
         // `flutter clean` exists, but according to its documentation it would
         // "Delete the build/ and .dart_tool/ directories" anyway. By doing this
         // directly, we don't require flutter to be installed.
 
-        remove_dirs(&self.dir, EPHEMERAL_DIRS, dry_run)
+        plan_removal(&self.dir, EPHEMERAL_DIRS)
+    }
+
+    fn ephemeral_dirs(&self) -> Vec<PathBuf> {
+        join_dirs(&self.dir, EPHEMERAL_DIRS)
+    }
+
+    fn categorized_ephemeral_dirs(&self) -> Vec<(PathBuf, Category)> {
+        CATEGORIES
+            .iter()
+            .map(|(dir, category)| (self.dir.join(dir), *category))
+            .collect()
     }
 
     fn status(&self) -> anyhow::Result<BuildStatus> {
         status_from_dirs(&self.dir, EPHEMERAL_DIRS)
     }
 
+    fn status_from_scan(&self, scan: &ProjectScan) -> anyhow::Result<BuildStatus> {
+        Ok(BuildStatus::from_paths(&self.ephemeral_paths(scan)))
+    }
+
+    fn reproducibility(&self) -> Reproducibility {
+        if is_real_dir(&self.dir.join(".dart_tool")) && !self.dir.join("pubspec.lock").is_file() {
+            Reproducibility::AtRisk(vec!["no pubspec.lock".to_owned()])
+        } else {
+            Reproducibility::Reproducible
+        }
+    }
+
     fn project_name(&self) -> Option<anyhow::Result<String>> {
         Some(Ok(self.pubspec.name.clone()))
     }
@@ -1,6 +1,9 @@
-use crate::build_tool_manager::BuildToolManager;
+use crate::{build_tool_manager::BuildToolManager, project::scan::ProjectScan};
 
-use super::{remove_dirs, status_from_dirs, BuildStatus, BuildTool, BuildToolKind, BuildToolProbe};
+use super::{
+    categorize_dirs, join_dirs, plan_removal, status_from_dirs, BuildStatus, BuildTool,
+    BuildToolProbe, Category, CleanStep, Removal,
+};
 use displaydoc::Display;
 use std::path::{Path, PathBuf};
 
@@ -27,8 +30,12 @@ impl BuildToolProbe for GradleProbe {
         }
     }
 
-    fn applies_to(&self, kind: BuildToolKind) -> bool {
-        kind == BuildToolKind::Gradle
+    fn id(&self) -> &str {
+        "gradle"
+    }
+
+    fn ephemeral_dirs(&self) -> Vec<&str> {
+        EPHEMERAL_DIRS.to_vec()
     }
 }
 
@@ -41,7 +48,7 @@ pub struct Gradle {
 static EPHEMERAL_DIRS: &[&str] = &["build"];
 
 impl BuildTool for Gradle {
-    fn clean_project(&mut self, dry_run: bool) -> anyhow::Result<()> {
+    fn clean_plan(&self, _removal: Removal) -> anyhow::Result<Vec<CleanStep>> {
         // `gradle clean`, i.e., the "clean" task, comes with gradle's base plugin. It
         // removes the build directory defined by $buildDir, which defaults to
         // $projectDir/build. Without executing Gradle, it's hard to figure out what
@@ -51,10 +58,22 @@ impl BuildTool for Gradle {
         // nobody does this anyway, but if they do, (2) they leverage dry-run and (3) use
         // Git to be able to restore the build directory in case this really happened.
 
-        remove_dirs(&self.dir, EPHEMERAL_DIRS, dry_run)
+        plan_removal(&self.dir, EPHEMERAL_DIRS)
+    }
+
+    fn ephemeral_dirs(&self) -> Vec<PathBuf> {
+        join_dirs(&self.dir, EPHEMERAL_DIRS)
+    }
+
+    fn categorized_ephemeral_dirs(&self) -> Vec<(PathBuf, Category)> {
+        categorize_dirs(&self.dir, EPHEMERAL_DIRS, Category::BuildOutputs).collect()
     }
 
     fn status(&self) -> anyhow::Result<BuildStatus> {
         status_from_dirs(&self.dir, EPHEMERAL_DIRS)
     }
+
+    fn status_from_scan(&self, scan: &ProjectScan) -> anyhow::Result<BuildStatus> {
+        Ok(BuildStatus::from_paths(&self.ephemeral_paths(scan)))
+    }
 }
@@ -1,12 +1,11 @@
 use crate::build_tool_manager::BuildToolManager;
 
-use super::{BuildTool, BuildToolKind, BuildToolProbe};
-use anyhow::{bail, Context};
-use displaydoc::Display;
-use std::{
-    path::{Path, PathBuf},
-    process::Command,
+use super::{
+    categorize_dirs, join_dirs, plan_removal, reason_to_keep, BuildTool, BuildToolProbe, Category,
+    CleanStep, Removal,
 };
+use displaydoc::Display;
+use std::path::{Path, PathBuf};
 
 pub fn register(manager: &mut BuildToolManager) {
     let probe = Box::new(MavenProbe {});
@@ -27,9 +26,16 @@ impl BuildToolProbe for MavenProbe {
         }
     }
 
-    fn applies_to(&self, kind: BuildToolKind) -> bool {
-        use BuildToolKind::*;
-        matches!(kind, Maven | Mvn)
+    fn id(&self) -> &str {
+        "maven"
+    }
+
+    fn aliases(&self) -> Vec<&str> {
+        vec!["mvn"]
+    }
+
+    fn ephemeral_dirs(&self) -> Vec<&str> {
+        EPHEMERAL_DIRS.to_vec()
     }
 }
 
@@ -39,29 +45,40 @@ pub struct Maven {
     dir: PathBuf,
 }
 
+/// The directory removed by `mvn clean`, unless configured otherwise.
+static EPHEMERAL_DIRS: &[&str] = &["target"];
+
 impl BuildTool for Maven {
-    fn clean_project(&mut self, dry_run: bool) -> anyhow::Result<()> {
-        let mut cmd = Command::new("mvn");
-        let cmd = cmd.arg("clean").current_dir(&self.dir);
-        if dry_run {
-            println!("{}: {:?}", self.dir.display(), cmd);
-        } else {
-            let status = cmd.status().with_context(|| {
-                format!(
-                    "Failed to execute {:?} for project at {}",
-                    cmd,
-                    self.dir.display()
-                )
-            })?;
-            if !status.success() {
-                bail!(
-                    "Unexpected exit code {} for {:?} for project at {}",
-                    status,
-                    cmd,
-                    self.dir.display()
-                );
-            }
+    fn clean_plan(&self, removal: Removal) -> anyhow::Result<Vec<CleanStep>> {
+        let existing_dirs: Vec<PathBuf> = self
+            .ephemeral_dirs()
+            .into_iter()
+            .filter(|dir| dir.exists())
+            .collect();
+
+        // `mvn clean` deletes all of the files, so the directory is removed
+        // directly instead if it should be restorable, or if it must be kept
+        if removal.is_restorable()
+            || existing_dirs
+                .iter()
+                .any(|dir| reason_to_keep(dir).is_some())
+        {
+            return plan_removal(&self.dir, EPHEMERAL_DIRS);
         }
-        Ok(())
+
+        Ok(vec![CleanStep::run(
+            "mvn",
+            &["clean".to_owned()],
+            &self.dir,
+            existing_dirs,
+        )])
+    }
+
+    fn ephemeral_dirs(&self) -> Vec<PathBuf> {
+        join_dirs(&self.dir, EPHEMERAL_DIRS)
+    }
+
+    fn categorized_ephemeral_dirs(&self) -> Vec<(PathBuf, Category)> {
+        categorize_dirs(&self.dir, EPHEMERAL_DIRS, Category::BuildOutputs).collect()
     }
 }
@@ -1,8 +1,16 @@
 use displaydoc::Display;
 
-use super::{remove_dirs, status_from_dirs, BuildStatus, BuildTool, BuildToolKind, BuildToolProbe};
-use crate::build_tool_manager::BuildToolManager;
-use std::path::{Path, PathBuf};
+use regex::Regex;
+
+use super::{
+    is_real_dir, join_dirs, plan_removal, status_from_dirs, BuildStatus, BuildTool, BuildToolProbe,
+    Category, CleanStep, Removal, Reproducibility,
+};
+use crate::{build_tool_manager::BuildToolManager, project::scan::ProjectScan};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
 pub fn register(manager: &mut BuildToolManager) {
     let probe = Box::new(MixProbe {});
@@ -21,9 +29,16 @@ impl BuildToolProbe for MixProbe {
         }
     }
 
-    fn applies_to(&self, kind: BuildToolKind) -> bool {
-        use BuildToolKind::*;
-        matches!(kind, Mix | Elixir | Ex | Exs)
+    fn id(&self) -> &str {
+        "mix"
+    }
+
+    fn aliases(&self) -> Vec<&str> {
+        vec!["elixir", "ex", "exs"]
+    }
+
+    fn ephemeral_dirs(&self) -> Vec<&str> {
+        EPHEMERAL_DIRS.to_vec()
     }
 }
 
@@ -43,8 +58,14 @@ impl Mix {
 
 static EPHEMERAL_DIRS: &[&str] = &["_build", "deps", ".elixir_ls"];
 
+static CATEGORIES: &[(&str, Category)] = &[
+    ("_build", Category::BuildOutputs),
+    ("deps", Category::Dependencies),
+    (".elixir_ls", Category::Tooling),
+];
+
 impl BuildTool for Mix {
-    fn clean_project(&mut self, dry_run: bool) -> anyhow::Result<()> {
+    fn clean_plan(&self, _removal: Removal) -> anyhow::Result<Vec<CleanStep>> {
         // `mix clean --deps` exists, but
         // - it needs to be installed
         // - it needs to match the version used in the project
@@ -54,13 +75,43 @@ impl BuildTool for Mix {
         // works just as well (better?), is faster, and doesn't require mix to
         // be installed.
 
-        remove_dirs(&self.dir, EPHEMERAL_DIRS, dry_run)
+        plan_removal(&self.dir, EPHEMERAL_DIRS)
+    }
+
+    fn ephemeral_dirs(&self) -> Vec<PathBuf> {
+        join_dirs(&self.dir, EPHEMERAL_DIRS)
+    }
+
+    fn categorized_ephemeral_dirs(&self) -> Vec<(PathBuf, Category)> {
+        CATEGORIES
+            .iter()
+            .map(|(dir, category)| (self.dir.join(dir), *category))
+            .collect()
     }
 
     fn status(&self) -> anyhow::Result<BuildStatus> {
         status_from_dirs(&self.dir, EPHEMERAL_DIRS)
     }
 
+    fn status_from_scan(&self, scan: &ProjectScan) -> anyhow::Result<BuildStatus> {
+        Ok(BuildStatus::from_paths(&self.ephemeral_paths(scan)))
+    }
+
+    fn reproducibility(&self) -> Reproducibility {
+        if !is_real_dir(&self.dir.join("deps")) {
+            return Reproducibility::Reproducible;
+        }
+
+        let mut risks = Vec::new();
+        if !self.dir.join("mix.lock").is_file() {
+            risks.push("no mix.lock".to_owned());
+        }
+        if let Ok(mix_exs) = fs::read_to_string(self.dir.join("mix.exs")) {
+            risks.extend(dependency_risks(&mix_exs));
+        }
+        Reproducibility::from_risks(risks)
+    }
+
     fn project_name(&self) -> Option<anyhow::Result<String>> {
         // mix.exs, which contains the project name, is not easy to parse without Elixir.
         // While `mix run -e 'IO.puts(Mix.Project.config[:app])'` would work, it would
@@ -70,6 +121,26 @@ impl BuildTool for Mix {
     }
 }
 
+/// Dependencies declared in `mix.exs` that aren't fetched from Hex, and might
+/// therefore change or disappear.
+///
+/// As `mix.exs` can't be evaluated without Elixir, the dependency tuples are
+/// matched textually, e.g., `{:plug, github: "elixir-plug/plug"}`.
+fn dependency_risks(mix_exs: &str) -> Vec<String> {
+    let dependency =
+        Regex::new(r#"\{\s*:(\w+)\s*,[^}]*?\b(git|github|path):"#).expect("regex is valid");
+    dependency
+        .captures_iter(mix_exs)
+        .map(|captures| {
+            let source = match &captures[2] {
+                "path" => "path",
+                _ => "Git",
+            };
+            format!("{} is a {source} dependency", &captures[1])
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod test {
     use assert_fs::{
@@ -80,6 +151,29 @@ mod test {
 
     use super::*;
 
+    #[test]
+    fn dependencies_not_from_hex_are_risks() {
+        let mix_exs = r#"
+            defp deps do
+              [
+                {:jason, "~> 1.4"},
+                {:plug, github: "elixir-plug/plug", branch: "main"},
+                {:phoenix, "~> 1.7", git: "https://github.com/phoenixframework/phoenix.git"},
+                {:my_lib, path: "../my_lib"}
+              ]
+            end
+        "#;
+
+        assert_eq!(
+            dependency_risks(mix_exs),
+            vec![
+                "plug is a Git dependency",
+                "phoenix is a Git dependency",
+                "my_lib is a path dependency",
+            ]
+        );
+    }
+
     #[test]
     fn elixir_ls_cache_is_removed_even_if_not_gitignored() {
         let root = TempDir::new().unwrap();
@@ -102,10 +196,10 @@ mod test {
 
         // In the normal case, status should report back that the project is not clean:
         let normal_status = Mix::new(&root.child("normal")).status().unwrap();
-        assert_matches!(normal_status, BuildStatus::Built{freeable_bytes} if freeable_bytes > 0);
+        assert_matches!(normal_status, BuildStatus::Built{freeable_bytes, ..} if freeable_bytes > 0);
 
         // If not ignored, the behavior is the same:
         let not_ignored_status = Mix::new(&root.child("not-ignored")).status().unwrap();
-        assert_matches!(not_ignored_status, BuildStatus::Built{freeable_bytes} if freeable_bytes > 0);
+        assert_matches!(not_ignored_status, BuildStatus::Built{freeable_bytes, ..} if freeable_bytes > 0);
     }
 }
@@ -1,64 +1,79 @@
-use std::{fs, path::Path};
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    process::Command,
+    str::FromStr,
+    sync::Mutex,
+};
 
-use clap::ArgEnum;
-use displaydoc::Display;
+use anyhow::{bail, Context};
 
-use crate::fs::dir_size;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    fs::{find_mount_point, format_size, DiskUsage, Hardlinks},
+    project::{scan::ProjectScan, vcs::VersionControlSystem},
+    staging::stage,
+    trash::trash,
+};
 
 pub mod cargo;
+pub mod custom;
 pub mod elm;
+pub mod external;
 pub mod flutter;
 pub mod gradle;
 pub mod maven;
 pub mod mix;
 pub mod npm;
 
-#[derive(Debug, Display, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ArgEnum)]
-pub enum BuildToolKind {
-    /// Cargo
-    Cargo,
-    /// Rust
-    Rust,
-    /// rs
-    Rs,
-
-    /// Elm
-    Elm,
-
-    /// Flutter
-    Flutter,
-
-    /// Gradle
-    Gradle,
+pub trait BuildToolProbe: std::fmt::Debug + Send + Sync {
+    /// Returns a [`BuildTool`] instance if configured in the given directory.
+    fn probe(&self, dir: &Path) -> Option<Box<dyn BuildTool>>;
 
-    /// Maven
-    Maven,
-    /// mvn
-    Mvn,
+    /// The canonical name of the build tool, used with `--type`, e.g. "cargo".
+    fn id(&self) -> &str;
 
-    /// Mix
-    Mix,
-    /// Elixir
-    Elixir,
-    /// ex
-    Ex,
-    /// exs
-    Exs,
+    /// Alternative names that can be used with `--type`, e.g. "rust".
+    fn aliases(&self) -> Vec<&str> {
+        Vec::new()
+    }
 
-    /// NPM
-    Npm,
-}
+    /// The directories (relative to a project directory) that are typically
+    /// removed when cleaning a project of this kind.
+    ///
+    /// Informational only; used to describe the build tool to the user.
+    fn ephemeral_dirs(&self) -> Vec<&str> {
+        Vec::new()
+    }
 
-pub trait BuildToolProbe: std::fmt::Debug {
-    /// Returns a [`BuildTool`] instance if configured in the given directory.
-    fn probe(&self, dir: &Path) -> Option<Box<dyn BuildTool>>;
+    /// Names of directories that only ever hold dependencies, wherever they
+    /// are, e.g. `node_modules` for NPM.
+    ///
+    /// Such directories are never searched for projects, even if they don't
+    /// belong to a project that was found, as the packages within them are
+    /// dependencies rather than projects.
+    fn dependency_dirs(&self) -> Vec<&str> {
+        Vec::new()
+    }
 
     /// Whether the build tool matches a given build tool name or project type.
-    fn applies_to(&self, kind: BuildToolKind) -> bool;
+    ///
+    /// By default, the name is compared to [`BuildToolProbe::id`] and
+    /// [`BuildToolProbe::aliases`], ignoring case.
+    fn applies_to(&self, name: &str) -> bool {
+        self.id().eq_ignore_ascii_case(name)
+            || self
+                .aliases()
+                .iter()
+                .any(|alias| alias.eq_ignore_ascii_case(name))
+    }
 }
 
-pub trait BuildTool: std::fmt::Debug + std::fmt::Display {
-    /// Clean the project.
+pub trait BuildTool: std::fmt::Debug + std::fmt::Display + Send {
+    /// How to clean the project.
     ///
     /// Depending on the build tool represented, this should preferably invoke
     /// the tool itself, calling its "clean" command. If that's not possible,
@@ -67,14 +82,78 @@ pub trait BuildTool: std::fmt::Debug + std::fmt::Display {
     /// removing well-known directories itself (e.g., the `node_modules`
     /// directory for NPM).
     ///
-    /// If `dry_run` is true, no files are changed. Instead, a description on
-    /// what would happen is printed to stdout.
-    fn clean_project(&mut self, dry_run: bool) -> anyhow::Result<()>;
+    /// Nothing is changed; the caller shows the plan for a dry run, or has a
+    /// [`Remover`] execute it. If the files are to be moved rather than
+    /// deleted (see [`Removal::is_restorable`]), or if any of them must be
+    /// kept (see [`reason_to_keep`]), implementations should remove the files
+    /// themselves rather than invoking the tool.
+    fn clean_plan(&self, removal: Removal) -> anyhow::Result<Vec<CleanStep>>;
 
     fn status(&self) -> anyhow::Result<BuildStatus> {
         Ok(BuildStatus::Unknown)
     }
 
+    /// Like [`BuildTool::status`], but based on a scan of the project
+    /// directory that already measured the [`BuildTool::ephemeral_dirs`].
+    fn status_from_scan(&self, _scan: &ProjectScan) -> anyhow::Result<BuildStatus> {
+        self.status()
+    }
+
+    /// The directories that are removed when cleaning the project, along
+    /// with any files that are removed, so they are measured as well.
+    ///
+    /// The directories don't have to exist. They are never searched for
+    /// (nested) projects.
+    fn ephemeral_dirs(&self) -> Vec<PathBuf> {
+        Vec::new()
+    }
+
+    /// The ephemeral directories and files that exist, with their sizes, i.e.,
+    /// exactly what cleaning the project would remove.
+    ///
+    /// The default implementation looks up the [`BuildTool::ephemeral_dirs`]
+    /// in `scan`, which measured them. Symbolic links are included with a
+    /// size of zero, as only the links are removed, and directories that
+    /// contain mount points are left out, as they're kept.
+    fn ephemeral_paths(&self, scan: &ProjectScan) -> Vec<EphemeralPath> {
+        let categories: BTreeMap<PathBuf, Category> =
+            self.categorized_ephemeral_dirs().into_iter().collect();
+        self.ephemeral_dirs()
+            .into_iter()
+            .filter_map(|path| {
+                let usage = match scan.ephemeral.get(&path) {
+                    Some(usage) => *usage,
+                    None if scan.symlinked_ephemeral_dirs.contains(&path) => DiskUsage::default(),
+                    None => return None,
+                };
+                let category = categories.get(&path).copied();
+                Some(EphemeralPath {
+                    path,
+                    category,
+                    usage,
+                })
+            })
+            .collect()
+    }
+
+    /// The [`BuildTool::ephemeral_dirs`] with what kind of content they
+    /// hold, used to clean selected categories only.
+    ///
+    /// Directories not listed are uncategorized; they're only removed when
+    /// all categories are cleaned.
+    fn categorized_ephemeral_dirs(&self) -> Vec<(PathBuf, Category)> {
+        Vec::new()
+    }
+
+    /// Whether the dependencies removed when cleaning the project can be
+    /// restored exactly as they were, e.g., because they're pinned by a
+    /// lockfile.
+    ///
+    /// Projects at risk are only cleaned if confirmed explicitly.
+    fn reproducibility(&self) -> Reproducibility {
+        Reproducibility::Unknown
+    }
+
     /// The project's name as parsed from build tool configuration.
     ///
     /// Returns None if the project has no name configured, or in case the build
@@ -85,49 +164,586 @@ pub trait BuildTool: std::fmt::Debug + std::fmt::Display {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum BuildStatus {
     /// There are no build artifacts or dependency that could be cleaned up.
     Clean,
     /// The project could be cleaned up, potentially freeing up `freeable_bytes`
-    /// bytes.
-    Built { freeable_bytes: u64 },
+    /// bytes on disk. The files to be removed have a total size of
+    /// `apparent_bytes`; see [`DiskUsage`].
+    Built {
+        freeable_bytes: u64,
+        apparent_bytes: u64,
+    },
     /// The status cannot be determined.
     Unknown,
 }
 
-//
-// Utils for build tools
-//
+impl BuildStatus {
+    /// The status of a project with the given ephemeral paths; see
+    /// [`BuildTool::ephemeral_paths`].
+    pub fn from_paths(paths: &[EphemeralPath]) -> Self {
+        let mut usage = DiskUsage::default();
+        for path in paths {
+            usage += path.usage;
+        }
+        usage.into()
+    }
+}
 
-fn remove_dirs(project_dir: &Path, ephemeral_dirs: &[&str], dry_run: bool) -> anyhow::Result<()> {
-    for dir in ephemeral_dirs
-        .iter()
-        .map(|dirname| project_dir.join(dirname))
-        .filter(|dir| dir.is_dir())
-    {
-        if dry_run {
-            println!("rm -r '{}'", dir.display());
+impl From<DiskUsage> for BuildStatus {
+    fn from(usage: DiskUsage) -> Self {
+        match usage.apparent_bytes {
+            0 => BuildStatus::Clean,
+            apparent_bytes => BuildStatus::Built {
+                freeable_bytes: usage.freeable_bytes,
+                apparent_bytes,
+            },
+        }
+    }
+}
+
+/// An existing file or directory that cleaning a project removes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EphemeralPath {
+    pub path: PathBuf,
+    /// Uncategorized paths are only removed if all categories are cleaned.
+    pub category: Option<Category>,
+    #[serde(flatten)]
+    pub usage: DiskUsage,
+}
+
+/// What kind of content an ephemeral directory holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Category {
+    /// Compiled or generated files, which are cheap to recreate, e.g.,
+    /// `target`.
+    BuildOutputs,
+    /// Downloaded dependencies, which are expensive to download again, e.g.,
+    /// `node_modules`.
+    Dependencies,
+    /// Caches that speed up builds.
+    Cache,
+    /// Files of IDEs and other tooling, e.g., `.elixir_ls`.
+    Tooling,
+}
+
+impl Category {
+    pub const ALL: &'static [Category] = &[
+        Category::BuildOutputs,
+        Category::Dependencies,
+        Category::Cache,
+        Category::Tooling,
+    ];
+
+    /// The name used on the command line and in the JSON output.
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::BuildOutputs => "build-outputs",
+            Category::Dependencies => "dependencies",
+            Category::Cache => "cache",
+            Category::Tooling => "tooling",
+        }
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name().replace('-', " "))
+    }
+}
+
+impl FromStr for Category {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let category = match s.to_ascii_lowercase().as_str() {
+            "build-outputs" | "build" => Category::BuildOutputs,
+            "dependencies" | "deps" => Category::Dependencies,
+            "cache" => Category::Cache,
+            "tooling" | "ide" => Category::Tooling,
+            _ => bail!(
+                "unknown category {s:?}; expected one of {}",
+                Category::ALL
+                    .iter()
+                    .map(|category| category.name())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+        Ok(category)
+    }
+}
+
+/// Whether the dependencies removed when cleaning a project can be restored
+/// exactly as they were; see [`BuildTool::reproducibility`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reproducibility {
+    /// The build tool cannot tell.
+    Unknown,
+    /// Cleaning removes no dependencies that couldn't be restored exactly.
+    Reproducible,
+    /// Restoring the dependencies might give different results, or might not
+    /// work at all. Each reason is a short description, e.g., "no
+    /// package-lock.json".
+    AtRisk(Vec<String>),
+}
+
+impl Reproducibility {
+    /// Assesses the reasons found, if any.
+    pub fn from_risks(risks: Vec<String>) -> Self {
+        if risks.is_empty() {
+            Reproducibility::Reproducible
         } else {
-            fs::remove_dir_all(dir)?;
+            Reproducibility::AtRisk(risks)
+        }
+    }
+
+    /// The reasons why restoring the dependencies might not work as expected.
+    pub fn risks(&self) -> &[String] {
+        match self {
+            Reproducibility::AtRisk(risks) => risks,
+            Reproducibility::Unknown | Reproducibility::Reproducible => &[],
         }
     }
+}
 
-    Ok(())
+/// A step of cleaning a project; see [`BuildTool::clean_plan`].
+///
+/// The expected sizes are filled in by [`Project::clean_plan`](crate::Project::clean_plan).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum CleanStep {
+    /// Removes a file or directory, including its contents. If it's a
+    /// symbolic link, only the link is removed.
+    Remove {
+        path: PathBuf,
+        #[serde(flatten)]
+        usage: DiskUsage,
+    },
+    /// Runs a command, e.g., the build tool's own clean command, which is
+    /// expected to remove the given paths.
+    Run {
+        program: String,
+        args: Vec<String>,
+        dir: PathBuf,
+        removes: Vec<PathBuf>,
+        #[serde(flatten)]
+        usage: DiskUsage,
+    },
+    /// Leaves a file or directory that would be removed otherwise, as it's
+    /// not safe to remove it; see [`reason_to_keep`].
+    Keep { path: PathBuf, reason: String },
 }
 
-fn status_from_dirs(project_dir: &Path, ephemeral_dirs: &[&str]) -> anyhow::Result<BuildStatus> {
-    let size: u64 = ephemeral_dirs
+impl CleanStep {
+    pub fn remove(path: PathBuf) -> Self {
+        CleanStep::Remove {
+            path,
+            usage: DiskUsage::default(),
+        }
+    }
+
+    pub fn run(program: &str, args: &[String], dir: &Path, removes: Vec<PathBuf>) -> Self {
+        CleanStep::Run {
+            program: program.to_owned(),
+            args: args.to_vec(),
+            dir: dir.to_owned(),
+            removes,
+            usage: DiskUsage::default(),
+        }
+    }
+
+    /// How much disk space the step is expected to free up.
+    pub fn usage(&self) -> DiskUsage {
+        match self {
+            CleanStep::Remove { usage, .. } | CleanStep::Run { usage, .. } => *usage,
+            CleanStep::Keep { .. } => DiskUsage::default(),
+        }
+    }
+}
+
+/// Renders the step like a shell command.
+impl fmt::Display for CleanStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CleanStep::Remove { path, .. } if is_symlink(path) => {
+                write!(f, "rm {}", quote_path(path))?
+            }
+            CleanStep::Remove { path, .. } => write!(f, "rm -r {}", quote_path(path))?,
+            CleanStep::Run {
+                program, args, dir, ..
+            } => {
+                write!(f, "(cd {} && {}", quote_path(dir), shell_quote(program))?;
+                for arg in args {
+                    write!(f, " {}", shell_quote(arg))?;
+                }
+                write!(f, ")")?
+            }
+            CleanStep::Keep { path, reason } => {
+                return write!(f, "# keeping {}, as it {reason}", quote_path(path))
+            }
+        }
+        match self.usage().freeable_bytes {
+            0 => Ok(()),
+            bytes => write!(f, "  # {}", format_size(bytes)),
+        }
+    }
+}
+
+/// How files are removed when cleaning a project.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Removal {
+    /// Files are deleted right away.
+    Delete,
+    /// Files are moved to the trash; see [`crate::trash`].
+    Trash,
+    /// Files are moved to a staging area, and deleted by a later run; see
+    /// [`crate::staging`].
+    Stage,
+}
+
+impl Removal {
+    /// Whether files are moved rather than deleted, so they can be restored.
+    pub fn is_restorable(self) -> bool {
+        matches!(self, Removal::Trash | Removal::Stage)
+    }
+}
+
+/// Executes the [`CleanStep`]s planned by build tools, remembering what was
+/// removed.
+///
+/// Files and directories that contain anything that is not ignored by
+/// version control, or mount points, are kept; see [`reason_to_keep`].
+/// Symbolic links are removed, but not what they point to.
+#[derive(Debug)]
+pub struct Remover {
+    removal: Removal,
+    removed: Mutex<Vec<Removed>>,
+    kept: Mutex<Vec<Kept>>,
+}
+
+/// A file or directory that was removed while cleaning a project.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Removed {
+    pub path: PathBuf,
+    pub removal: Removal,
+    /// Where it has been moved to, if it was moved to the trash or staged.
+    pub backup: Option<PathBuf>,
+}
+
+/// A file or directory that was not removed, as it's not safe to do so.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Kept {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+impl Remover {
+    pub fn new(removal: Removal) -> Self {
+        Self {
+            removal,
+            removed: Mutex::default(),
+            kept: Mutex::default(),
+        }
+    }
+
+    pub fn removal(&self) -> Removal {
+        self.removal
+    }
+
+    /// Executes a step of cleaning a project.
+    ///
+    /// Paths to remove have been checked for a [`reason_to_keep`] them while
+    /// planning, so they're not checked again.
+    pub fn execute(&self, step: &CleanStep) -> anyhow::Result<()> {
+        match step {
+            CleanStep::Remove { path, .. } => self.remove_unchecked(path),
+            CleanStep::Run {
+                program,
+                args,
+                dir,
+                removes,
+                ..
+            } => {
+                let mut cmd = Command::new(program);
+                let cmd = cmd.args(args).current_dir(dir);
+                let status = cmd.status().with_context(|| {
+                    format!(
+                        "Failed to execute {:?} for project at {}",
+                        cmd,
+                        dir.display()
+                    )
+                })?;
+                if !status.success() {
+                    bail!(
+                        "Unexpected exit code {} for {:?} for project at {}",
+                        status,
+                        cmd,
+                        dir.display()
+                    );
+                }
+                for path in removes.iter().filter(|path| !path.exists()) {
+                    self.record_removed(path);
+                }
+                Ok(())
+            }
+            CleanStep::Keep { path, reason } => {
+                self.keep(path, reason.clone());
+                Ok(())
+            }
+        }
+    }
+
+    /// Removes a file or directory, including its contents, unless there's
+    /// a [reason to keep it](reason_to_keep).
+    pub fn remove(&self, path: &Path) -> anyhow::Result<()> {
+        match reason_to_keep(path) {
+            Some(reason) => {
+                self.keep(path, reason);
+                Ok(())
+            }
+            None => self.remove_unchecked(path),
+        }
+    }
+
+    fn remove_unchecked(&self, path: &Path) -> anyhow::Result<()> {
+        // Removing the link is enough, as what it points to might be shared
+        // with other projects
+        if is_symlink(path) {
+            fs::remove_file(path).or_else(|_| fs::remove_dir(path))?;
+            self.push(Removed {
+                path: path.to_owned(),
+                removal: Removal::Delete,
+                backup: None,
+            });
+            return Ok(());
+        }
+
+        let backup = match self.removal {
+            Removal::Trash => Some(trash(path)?.trashed_path),
+            Removal::Stage => Some(stage(path)?),
+            Removal::Delete if path.is_dir() => {
+                fs::remove_dir_all(path)?;
+                None
+            }
+            Removal::Delete => {
+                fs::remove_file(path)?;
+                None
+            }
+        };
+        self.push(Removed {
+            path: path.to_owned(),
+            removal: self.removal,
+            backup,
+        });
+        Ok(())
+    }
+
+    /// Remembers that `path` was removed by other means, e.g., by the build
+    /// tool's own clean command.
+    pub fn record_removed(&self, path: &Path) {
+        self.push(Removed {
+            path: path.to_owned(),
+            removal: Removal::Delete,
+            backup: None,
+        });
+    }
+
+    /// Returns what was removed since the last call.
+    pub fn take_removed(&self) -> Vec<Removed> {
+        std::mem::take(&mut *self.removed.lock().expect("not poisoned"))
+    }
+
+    /// Returns what was kept since the last call.
+    pub fn take_kept(&self) -> Vec<Kept> {
+        std::mem::take(&mut *self.kept.lock().expect("not poisoned"))
+    }
+
+    fn push(&self, removed: Removed) {
+        self.removed.lock().expect("not poisoned").push(removed);
+    }
+
+    fn keep(&self, path: &Path, reason: String) {
+        self.kept.lock().expect("not poisoned").push(Kept {
+            path: path.to_owned(),
+            reason,
+        });
+    }
+}
+
+/// Why `path` must not be removed, if it mustn't.
+///
+/// Files that are tracked by version control, or untracked but not ignored,
+/// are not merely build artifacts; think of generated sources or checked-in
+/// build outputs. So neither they nor the directories that contain them are
+/// removed.
+///
+/// Directories that are or contain mount points are kept as well, as removing
+/// them would affect another file system.
+pub fn reason_to_keep(path: &Path) -> Option<String> {
+    if !is_symlink(path) {
+        match find_mount_point(path) {
+            Some(mount_point) if mount_point == path => {
+                return Some("it is a mount point".to_owned())
+            }
+            Some(mount_point) => {
+                return Some(format!(
+                    "contains the mount point {}",
+                    mount_point.display()
+                ))
+            }
+            None => {}
+        }
+    }
+
+    let vcs = match VersionControlSystem::try_from(path) {
+        Ok(Some(vcs)) => vcs,
+        Ok(None) => return None,
+        Err(e) => return Some(format!("{e:#}")),
+    };
+    match vcs.unignored_files(path) {
+        Ok(files) => files.first().map(|file| {
+            format!(
+                "{} not ignored by {}, e.g., {}",
+                match files.len() {
+                    1 => "1 file".to_owned(),
+                    n => format!("{n} files"),
+                },
+                vcs.name(),
+                file.display()
+            )
+        }),
+        Err(e) => Some(format!(
+            "failed to check for files tracked by {}: {e:#}",
+            vcs.name()
+        )),
+    }
+}
+
+// Utils for build tools
+//
+
+/// Quotes `word` for a POSIX shell, unless it consists of characters that
+/// don't need quoting only.
+fn shell_quote(word: &str) -> Cow<'_, str> {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+    if !word.is_empty() && word.chars().all(is_safe) {
+        word.into()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''")).into()
+    }
+}
+
+/// Quotes `path` for a POSIX shell, even if it doesn't need quoting.
+fn quote_path(path: &Path) -> String {
+    format!("'{}'", path.display().to_string().replace('\'', r"'\''"))
+}
+
+fn is_symlink(path: &Path) -> bool {
+    fs::symlink_metadata(path).map_or(false, |metadata| metadata.file_type().is_symlink())
+}
+
+/// Whether `path` is a directory, and not merely a symbolic link to one.
+///
+/// Only such dependency directories are at risk, as only the link is removed
+/// otherwise.
+fn is_real_dir(path: &Path) -> bool {
+    fs::symlink_metadata(path).map_or(false, |metadata| metadata.is_dir())
+}
+
+/// Measures those of `paths` that exist and would be removed, using the sizes
+/// found by `scan` where available; see [`BuildTool::ephemeral_paths`].
+fn measure_paths(
+    paths: impl IntoIterator<Item = PathBuf>,
+    category: Option<Category>,
+    scan: &ProjectScan,
+) -> Vec<EphemeralPath> {
+    paths
+        .into_iter()
+        .filter(|path| fs::symlink_metadata(path).is_ok())
+        .filter(|path| {
+            !scan
+                .ephemeral_mount_points
+                .iter()
+                .any(|mount_point| mount_point.starts_with(path))
+        })
+        .map(|path| {
+            // Paths that weren't measured, e.g., symbolic links, free up
+            // nothing
+            let usage = scan.ephemeral.get(&path).copied().unwrap_or_default();
+            EphemeralPath {
+                path,
+                category,
+                usage,
+            }
+        })
+        .collect()
+}
+
+fn categorize_dirs(
+    project_dir: &Path,
+    ephemeral_dirs: &[&str],
+    category: Category,
+) -> impl Iterator<Item = (PathBuf, Category)> {
+    join_dirs(project_dir, ephemeral_dirs)
+        .into_iter()
+        .map(move |dir| (dir, category))
+}
+
+fn join_dirs(project_dir: &Path, ephemeral_dirs: &[&str]) -> Vec<PathBuf> {
+    ephemeral_dirs
         .iter()
         .map(|dirname| project_dir.join(dirname))
+        .collect()
+}
+
+/// Plans removing those of the `ephemeral_dirs` that exist.
+fn plan_removal(project_dir: &Path, ephemeral_dirs: &[&str]) -> anyhow::Result<Vec<CleanStep>> {
+    Ok(join_dirs(project_dir, ephemeral_dirs)
+        .into_iter()
         .filter(|dir| dir.is_dir())
-        .map(|dir| dir_size(&dir))
-        .sum();
+        .map(CleanStep::remove)
+        .collect())
+}
 
-    let status = match size {
-        0 => BuildStatus::Clean,
-        freeable_bytes => BuildStatus::Built { freeable_bytes },
-    };
+/// The status of a build tool that removes the `ephemeral_dirs`, measured on
+/// its own rather than as part of a run; see [`BuildTool::status`].
+///
+/// Fails if any of the files can't be read, as the result would be
+/// misleading otherwise.
+fn status_from_dirs(project_dir: &Path, ephemeral_dirs: &[&str]) -> anyhow::Result<BuildStatus> {
+    let dirs = join_dirs(project_dir, ephemeral_dirs);
+    let scan = ProjectScan::of_ephemeral(&dirs, &Hardlinks::default());
+    if let Some(error) = scan.errors.first() {
+        bail!("Failed to measure {}: {error}", project_dir.display());
+    }
+    Ok(scan.status_of(&dirs))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
 
-    Ok(status)
+    #[test]
+    fn renders_steps_as_quoted_shell_commands() {
+        let step = CleanStep::run(
+            "mvn",
+            &[
+                "clean".to_owned(),
+                "-Dname=it's".to_owned(),
+                "a b".to_owned(),
+            ],
+            Path::new("/home/me/it's here"),
+            Vec::new(),
+        );
+        assert_eq!(
+            step.to_string(),
+            r"(cd '/home/me/it'\''s here' && mvn clean '-Dname=it'\''s' 'a b')"
+        );
+
+        let step = CleanStep::remove(PathBuf::from("/home/me/it's here/target"));
+        assert_eq!(step.to_string(), r"rm -r '/home/me/it'\''s here/target'");
+    }
 }
@@ -1,9 +1,14 @@
 use displaydoc::Display;
-use tracing::debug;
 
-use super::{remove_dirs, status_from_dirs, BuildStatus, BuildTool, BuildToolKind, BuildToolProbe};
-use crate::build_tool_manager::BuildToolManager;
-use std::path::{Path, PathBuf};
+use super::{
+    categorize_dirs, is_real_dir, join_dirs, plan_removal, status_from_dirs, BuildStatus,
+    BuildTool, BuildToolProbe, Category, CleanStep, Removal, Reproducibility,
+};
+use crate::{build_tool_manager::BuildToolManager, project::scan::ProjectScan};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
 pub fn register(manager: &mut BuildToolManager) {
     let probe = Box::new(NpmProbe {});
@@ -15,13 +20,6 @@ pub struct NpmProbe;
 
 impl BuildToolProbe for NpmProbe {
     fn probe(&self, dir: &Path) -> Option<Box<dyn BuildTool>> {
-        // If we're within a node_modules directory, we assume this is a
-        // dependency and shouldn't be cleansed by itself.
-        if dir.components().any(|x| x.as_os_str() == "node_modules") {
-            debug!("ignoring directory within node_modules dir at {:?}", dir);
-            return None;
-        }
-
         if dir.join("package.json").is_file() {
             Some(Box::new(Npm {
                 dir: dir.to_owned(),
@@ -31,8 +29,16 @@ impl BuildToolProbe for NpmProbe {
         }
     }
 
-    fn applies_to(&self, kind: BuildToolKind) -> bool {
-        kind == BuildToolKind::Npm
+    fn id(&self) -> &str {
+        "npm"
+    }
+
+    fn ephemeral_dirs(&self) -> Vec<&str> {
+        EPHEMERAL_DIRS.to_vec()
+    }
+
+    fn dependency_dirs(&self) -> Vec<&str> {
+        EPHEMERAL_DIRS.to_vec()
     }
 }
 
@@ -44,13 +50,133 @@ pub struct Npm {
 
 static EPHEMERAL_DIRS: &[&str] = &["node_modules"];
 
+/// The lockfiles of NPM, Yarn, pnpm and Bun, which pin the dependencies.
+static LOCKFILES: &[&str] = &[
+    "package-lock.json",
+    "npm-shrinkwrap.json",
+    "yarn.lock",
+    "pnpm-lock.yaml",
+    "bun.lockb",
+];
+
+static DEPENDENCY_SECTIONS: &[&str] = &["dependencies", "devDependencies", "optionalDependencies"];
+
 impl BuildTool for Npm {
-    fn clean_project(&mut self, dry_run: bool) -> anyhow::Result<()> {
+    fn clean_plan(&self, _removal: Removal) -> anyhow::Result<Vec<CleanStep>> {
         // TODO: also delete build directory, depending on the language(s) used
-        remove_dirs(&self.dir, EPHEMERAL_DIRS, dry_run)
+        plan_removal(&self.dir, EPHEMERAL_DIRS)
+    }
+
+    fn ephemeral_dirs(&self) -> Vec<PathBuf> {
+        join_dirs(&self.dir, EPHEMERAL_DIRS)
+    }
+
+    fn categorized_ephemeral_dirs(&self) -> Vec<(PathBuf, Category)> {
+        categorize_dirs(&self.dir, EPHEMERAL_DIRS, Category::Dependencies).collect()
     }
 
     fn status(&self) -> anyhow::Result<BuildStatus> {
         status_from_dirs(&self.dir, EPHEMERAL_DIRS)
     }
+
+    fn status_from_scan(&self, scan: &ProjectScan) -> anyhow::Result<BuildStatus> {
+        Ok(BuildStatus::from_paths(&self.ephemeral_paths(scan)))
+    }
+
+    fn reproducibility(&self) -> Reproducibility {
+        if !is_real_dir(&self.dir.join("node_modules")) {
+            return Reproducibility::Reproducible;
+        }
+
+        let mut risks = Vec::new();
+        if !LOCKFILES.iter().any(|file| self.dir.join(file).is_file()) {
+            risks.push("no lockfile, e.g., package-lock.json or yarn.lock".to_owned());
+        }
+        if let Ok(package_json) = fs::read_to_string(self.dir.join("package.json")) {
+            risks.extend(dependency_risks(&package_json));
+        }
+        // patch-package keeps its patches in `patches`, and applies them to
+        // `node_modules` in a postinstall script
+        if self.dir.join("patches").is_dir() {
+            risks.push("node_modules is modified by patch-package".to_owned());
+        }
+        Reproducibility::from_risks(risks)
+    }
+}
+
+/// Dependencies declared in `package.json` that aren't fetched from the
+/// registry, and might therefore change or disappear.
+fn dependency_risks(package_json: &str) -> Vec<String> {
+    let manifest: serde_json::Value = match serde_json::from_str(package_json) {
+        Ok(manifest) => manifest,
+        Err(_) => return Vec::new(),
+    };
+    DEPENDENCY_SECTIONS
+        .iter()
+        .filter_map(|section| manifest.get(section)?.as_object())
+        .flatten()
+        .filter_map(|(name, spec)| {
+            let spec = spec.as_str()?;
+            let source = dependency_source(spec)?;
+            Some(format!("{name} is a {source} dependency"))
+        })
+        .collect()
+}
+
+/// What kind of dependency a version spec refers to, unless it's a version
+/// (range) from the registry.
+///
+/// See <https://docs.npmjs.com/cli/configuring-npm/package-json#dependencies>.
+fn dependency_source(spec: &str) -> Option<&'static str> {
+    let is_path = ["file:", "link:", ".", "/", "~/"]
+        .iter()
+        .any(|prefix| spec.starts_with(prefix));
+    let is_git = ["git:", "git+", "github:", "gitlab:", "bitbucket:", "gist:"]
+        .iter()
+        .any(|prefix| spec.starts_with(prefix))
+        // GitHub shorthand, e.g., "user/repo#branch"
+        || (!spec.contains(':') && spec.contains('/'));
+    if is_path {
+        Some("path")
+    } else if is_git {
+        Some("Git")
+    } else if spec.starts_with("http:") || spec.starts_with("https:") {
+        Some("URL")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dependencies_not_from_the_registry_are_risks() {
+        let package_json = r#"{
+            "name": "app",
+            "dependencies": {
+                "left-pad": "^1.3.0",
+                "aliased": "npm:left-pad@1.3.0",
+                "forked": "github:someone/forked",
+                "shorthand": "someone/shorthand#main",
+                "local": "file:../local",
+                "tarball": "https://example.com/tarball.tgz"
+            },
+            "devDependencies": {
+                "tools": "git+ssh://git@example.com/tools.git"
+            }
+        }"#;
+
+        assert_eq!(
+            dependency_risks(package_json),
+            vec![
+                "forked is a Git dependency",
+                "local is a path dependency",
+                "shorthand is a Git dependency",
+                "tarball is a URL dependency",
+                "tools is a Git dependency",
+            ]
+        );
+    }
 }
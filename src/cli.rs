@@ -4,11 +4,14 @@
 
 use std::path::PathBuf;
 
-use clap::Parser;
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand};
 use regex::Regex;
 use time::Duration;
 
-use crate::build_tools::BuildToolKind;
+use crate::{
+    build_tool_manager::BuildToolManager, build_tools::Category, config::Config,
+    project::keep::Keep,
+};
 
 /// Options
 #[derive(Parser, Debug)]
@@ -25,7 +28,8 @@ pub struct Cli {
     pub list: bool,
 
     /// Projects that were modified more recently than this are ignored.
-    /// With `--list`, this defaults to 0, otherwise to one month.
+    /// With `--list`, this defaults to 0, otherwise to one month (unless
+    /// configured otherwise).
     /// Examples: 1d = a day, 2w = two weeks, 1m = a month, 1y = a year.
     #[clap(value_name(r"DURATION"), short, long, parse(try_from_str=parse_duration))]
     pub min_stale: Option<Duration>,
@@ -34,11 +38,39 @@ pub struct Cli {
     /// Use more than once for multiple project types.
     /// By default, all known project types are considered.
     ///
+    /// Custom build tools and probe plugins can be selected by their name;
+    /// use `--list-types` to see all of them.
+    ///
     /// For example, to consider Cargo and NPM projects:
     ///
     /// makeclean -t cargo -t npm
-    #[clap(short = 't', long = "type", arg_enum)]
-    pub types: Vec<BuildToolKind>,
+    #[clap(short = 't', long = "type", value_name("TYPE"))]
+    pub types: Vec<String>,
+
+    /// Only clean directories of the given category.
+    /// Use more than once for multiple categories.
+    ///
+    /// The categories are build-outputs (e.g., `target`), dependencies (e.g.,
+    /// `node_modules`), cache, and tooling (e.g., `.elixir_ls`). Directories
+    /// of custom build tools without a category and of probe plugins are only
+    /// removed when all categories are cleaned. Sizes only count the selected
+    /// categories.
+    ///
+    /// For example, to keep downloaded dependencies:
+    ///
+    /// makeclean --only build-outputs
+    #[clap(long, value_name("CATEGORY"), conflicts_with("except"))]
+    pub only: Vec<Category>,
+
+    /// Clean all but the given category; see `--only`.
+    /// Use more than once for multiple categories.
+    #[clap(long, value_name("CATEGORY"))]
+    pub except: Vec<Category>,
+
+    /// Lists the known project types, their aliases and the directories
+    /// they clean up.
+    #[clap(long)]
+    pub list_types: bool,
 
     /// Dry run - prints what would happen but doesn't actually remove/change anything.
     #[clap(short = 'n', long)]
@@ -48,22 +80,280 @@ pub struct Cli {
     #[clap(long)]
     pub yes: bool,
 
+    /// Move cleaned directories to the trash instead of deleting them.
+    ///
+    /// They can be restored using the usual desktop tools, and the space
+    /// they take up is only freed once the trash is emptied.
+    #[clap(long, conflicts_with = "stage")]
+    pub trash: bool,
+
+    /// Move cleaned directories into a staging area instead of deleting them.
+    ///
+    /// Staged directories are deleted by a later run, once their grace
+    /// period is over (see `--grace-period`). Until then, they can be
+    /// restored using `makeclean restore`.
+    #[clap(long, overrides_with = "no_stage")]
+    pub stage: bool,
+
+    /// Don't stage cleaned directories, even if the configuration says so.
+    #[clap(long, overrides_with = "stage")]
+    pub no_stage: bool,
+
+    /// How long directories staged by this run are kept in the staging area
+    /// before a later run with `--stage` deletes them (defaults to a week,
+    /// unless configured otherwise).
+    /// Examples: 1d = a day, 2w = two weeks, 1m = a month, 1y = a year.
+    #[clap(value_name(r"DURATION"), long, parse(try_from_str=parse_duration))]
+    pub grace_period: Option<Duration>,
+
+    /// Also clean projects that contain files or directories that could not
+    /// be read, or that are in use by running processes, and remove
+    /// dependencies that might not be restored exactly without asking.
+    ///
+    /// Such projects are skipped by default, as what couldn't be read might
+    /// have been modified recently, and removing what a build, a development
+    /// server or an editor uses breaks those. On Linux, a project is in use if
+    /// a process has its working directory in it, or has any of its files
+    /// open or mapped into memory. Dependencies might not be restored exactly
+    /// if, e.g., there is no lockfile; removing them needs to be confirmed for
+    /// each project, and with --yes, such projects are skipped.
+    #[clap(long)]
+    pub force: bool,
+
     /// Additionally compress cleaned projects.
     ///
     /// After cleaning a project, its contents are moved into a tar.xz file. To
-    /// restore the project, use `tar` (which is probably already installed on
-    /// your system):
+    /// restore the project, use `makeclean undo`, or `tar` (which is probably
+    /// already installed on your system):
     ///
     /// cd path/to/project && tar -xaf project-name.tar.xz && rm project-name.tar.xz
-    #[clap(short = 'z', long)]
+    #[clap(short = 'z', long, overrides_with = "no_archive")]
     pub archive: bool,
 
+    /// Don't archive cleaned projects, even if the configuration says so.
+    #[clap(long, overrides_with = "archive")]
+    pub no_archive: bool,
+
+    /// The xz compression level used with `--archive` (0-9, defaults to 6).
+    #[clap(value_name("LEVEL"), long, parse(try_from_str=parse_compression_level))]
+    pub compression_level: Option<u32>,
+
+    /// Read settings from this file instead of `~/.config/makeclean/config.toml`.
+    ///
+    /// Settings from a `.makeclean.toml` file in the (first) directory to
+    /// search or any of its parents are applied on top, and command-line
+    /// arguments take precedence over both.
+    #[clap(value_name("FILE"), long, env = "MAKECLEAN_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Prints the effective configuration and exits.
+    #[clap(long)]
+    pub print_config: bool,
+
+    /// Directories that should not be searched (in addition to those
+    /// excluded in the configuration file).
+    /// Use more than once for multiple patterns.
+    ///
+    /// Patterns that contain no slash match directories of that name
+    /// anywhere; patterns starting with `/` or `~/` match absolute paths.
+    ///
+    /// For example, to skip all `vendor` directories and `~/code/keep-me`:
+    ///
+    /// makeclean --exclude vendor --exclude '~/code/keep-me'
+    #[clap(value_name("GLOB"), short = 'x', long)]
+    pub exclude: Vec<String>,
+
+    /// Don't search deeper than this many directories below the given
+    /// directories.
+    #[clap(value_name("DEPTH"), long)]
+    pub max_depth: Option<usize>,
+
+    /// Don't descend into directories on other file systems, e.g., mounted
+    /// network shares.
+    #[clap(long, overrides_with = "no_one_file_system")]
+    pub one_file_system: bool,
+
+    /// Descend into directories on other file systems, even if the
+    /// configuration says otherwise.
+    #[clap(long, overrides_with = "one_file_system")]
+    pub no_one_file_system: bool,
+
+    /// Follow symbolic links to directories while searching.
+    #[clap(long, overrides_with = "no_follow_symlinks")]
+    pub follow_symlinks: bool,
+
+    /// Don't follow symbolic links to directories, even if the configuration
+    /// says so.
+    #[clap(long, overrides_with = "follow_symlinks")]
+    pub no_follow_symlinks: bool,
+
+    /// Don't use or update the scan index.
+    ///
+    /// To speed up repeated runs, what is found is remembered in
+    /// `~/.cache/makeclean/index.json`. With `--list`, entries of projects
+    /// whose directories haven't changed are used instead of looking at all
+    /// of their files again. Cleaning doesn't use the index.
+    #[clap(long)]
+    pub no_cache: bool,
+
+    /// Also search hidden directories, e.g., `.worktrees`.
+    #[clap(long, overrides_with = "no_hidden")]
+    pub hidden: bool,
+
+    /// Don't search hidden directories, even if the configuration says so.
+    #[clap(long, overrides_with = "hidden")]
+    pub no_hidden: bool,
+
+    /// Also search directories that are ignored by `.gitignore`, `.ignore`
+    /// and similar files. Directories removed when cleaning a project are
+    /// never searched, regardless.
+    #[clap(long)]
+    pub no_ignore: bool,
+
+    /// Pinned projects from the configuration file.
+    #[clap(skip)]
+    pub keep: Vec<Keep>,
+
     /// Recursively searches for project in these directories
-    #[clap(default_value = ".")]
+    /// (defaults to the current directory).
     pub directories: Vec<PathBuf>,
+
+    /// Runs a command instead of cleaning projects.
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Commands other than cleaning projects.
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Restores what a previous run removed or archived, as far as possible.
+    ///
+    /// Every run records what it removes and archives in
+    /// `~/.local/state/makeclean/journal.jsonl`. Directories that were moved
+    /// to the trash (see `--trash`) and archived projects (see `--archive`)
+    /// can be restored, as long as the trash hasn't been emptied or the
+    /// archive removed. Deleted directories cannot be restored.
+    Undo(UndoArgs),
+
+    /// Restores the staged directories of a project (see `--stage`).
+    ///
+    /// Restores all directories that were staged for deletion from projects
+    /// in the given directory, including projects in its subdirectories.
+    Restore(RestoreArgs),
+}
+
+/// Arguments of `makeclean undo`.
+#[derive(Args, Debug)]
+pub struct UndoArgs {
+    /// Undo the most recent run that can still be undone (the default).
+    #[clap(long, conflicts_with = "run-id")]
+    pub last: bool,
+
+    /// The run to undo, as printed after cleaning.
+    pub run_id: Option<String>,
+}
+
+/// Arguments of `makeclean restore`.
+#[derive(Args, Debug)]
+pub struct RestoreArgs {
+    /// The project directory (defaults to the current directory).
+    #[clap(default_value = ".")]
+    pub directory: PathBuf,
+}
+
+impl Cli {
+    /// Parses the command-line arguments as far as possible, without printing
+    /// help or exiting on errors.
+    ///
+    /// This is used to find the configuration, which declares build tools
+    /// that must be known before the arguments are [parsed for
+    /// real](Cli::parse_with).
+    pub fn parse_leniently() -> Option<Self> {
+        let command = Self::command()
+            .disable_help_flag(true)
+            .disable_help_subcommand(true)
+            .disable_version_flag(true)
+            .ignore_errors(true);
+        let matches = command.try_get_matches().ok()?;
+        Self::from_arg_matches(&matches).ok()
+    }
+
+    /// Parses the command-line arguments.
+    ///
+    /// The help text of `--type` lists the project types known to
+    /// `build_tool_manager`.
+    pub fn parse_with(build_tool_manager: &BuildToolManager) -> Self {
+        let known_types = build_tool_manager
+            .probes()
+            .iter()
+            .map(|probe| {
+                let aliases = probe.aliases();
+                if aliases.is_empty() {
+                    probe.id().to_owned()
+                } else {
+                    format!("{} ({})", probe.id(), aliases.join(", "))
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let command = Self::command().mut_arg("types", |arg| {
+            let suffix = format!("[known types: {known_types}]");
+            // clap wants &'static str; this is done once per process
+            let help: &'static str = match arg.get_help() {
+                Some(help) => Box::leak(format!("{help} {suffix}").into_boxed_str()),
+                None => Box::leak(suffix.clone().into_boxed_str()),
+            };
+            let long_help: &'static str = match arg.get_long_help() {
+                Some(help) => Box::leak(format!("{help}\n\n{suffix}").into_boxed_str()),
+                None => help,
+            };
+            arg.help(help).long_help(long_help)
+        });
+
+        let matches = command.get_matches();
+        Self::from_arg_matches(&matches).unwrap_or_else(|e| e.exit())
+    }
+
+    /// Fills in settings from the effective configuration.
+    ///
+    /// `config` is expected to already contain the command-line arguments,
+    /// see [`Config::merge_cli`].
+    pub fn apply_config(&mut self, config: &Config) -> anyhow::Result<()> {
+        self.min_stale = config.min_stale()?;
+        self.types = config.types.clone();
+        self.archive = config.archive.enabled.unwrap_or(false);
+        self.compression_level = config.archive.compression_level;
+        self.stage = config.stage.enabled.unwrap_or(false) && !self.trash;
+        self.grace_period = config.grace_period()?;
+        self.exclude = config.exclude.clone();
+        self.max_depth = config.max_depth;
+        self.one_file_system = config.one_file_system.unwrap_or(false);
+        self.follow_symlinks = config.follow_symlinks.unwrap_or(false);
+        self.hidden = config.hidden.unwrap_or(false);
+        self.no_ignore = config.no_ignore.unwrap_or(false);
+        // Project paths are canonical, so the pinned paths must be too
+        self.keep = config
+            .keep
+            .iter()
+            .filter_map(|keep| {
+                let path = keep.path.as_ref()?.canonicalize().ok()?;
+                Some(Keep {
+                    path: Some(path),
+                    ..keep.clone()
+                })
+            })
+            .collect();
+        self.directories = if config.directories.is_empty() {
+            vec![PathBuf::from(".")]
+        } else {
+            config.directories.clone()
+        };
+        Ok(())
+    }
 }
 
-fn parse_duration(s: &str) -> anyhow::Result<Duration> {
+pub(crate) fn parse_duration(s: &str) -> anyhow::Result<Duration> {
     let captures = Regex::new(r"^(?P<n>\d+)(?P<unit>[dDwWmMyY])?$")
         .unwrap()
         .captures(s)
@@ -88,3 +378,10 @@ fn parse_duration(s: &str) -> anyhow::Result<Duration> {
     };
     Ok(duration)
 }
+
+fn parse_compression_level(s: &str) -> anyhow::Result<u32> {
+    match s.parse::<u32>() {
+        Ok(level) if level <= 9 => Ok(level),
+        _ => anyhow::bail!("Expected a compression level between 0 and 9, got {:?}", s),
+    }
+}
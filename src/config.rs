@@ -0,0 +1,509 @@
+//! Configuration files.
+//!
+//! Settings are read from the user's configuration file at
+//! `~/.config/makeclean/config.toml` (respecting `$XDG_CONFIG_HOME`) and from
+//! the nearest `.makeclean.toml` in the directory to search (the first one, if
+//! several are given) or any of its parents. Settings in `.makeclean.toml`
+//! take precedence over the user's configuration file, and command-line
+//! arguments take precedence over both.
+//!
+//! An example configuration file:
+//!
+//! ```toml
+//! min_stale = "2w"
+//! directories = ["~/code", "~/work"]
+//! types = ["cargo", "npm"]
+//! exclude = ["~/code/keep-me", "vendor"]
+//! max_depth = 5
+//! one_file_system = true
+//!
+//! [archive]
+//! enabled = false
+//! compression_level = 9
+//!
+//! [stage]
+//! enabled = true
+//! grace_period = "2w"
+//!
+//! [tools.gradle]
+//! enabled = false
+//! ```
+//!
+//! The `tools` sections only support disabling a build tool; other settings,
+//! like `min_stale`, apply to all build tools alike.
+//!
+//! Additional build tools can be declared in `[[custom_tools]]` sections, see
+//! [`crate::build_tools::custom`], or recognized by external programs listed
+//! in `external_probes`, see [`crate::build_tools::external`].
+//!
+//! Projects can be pinned in `[[keep]]` sections, see [`crate::project::keep`].
+
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use time::Duration;
+use tracing::debug;
+
+use crate::{
+    build_tools::custom::{CustomProbe, CustomToolConfig},
+    cli::{parse_duration, Cli},
+    project::keep::Keep,
+};
+
+/// The name of the configuration file that is looked up in the current
+/// directory and its parents.
+pub const LOCAL_CONFIG_FILE_NAME: &str = ".makeclean.toml";
+
+/// Settings that can be put into a configuration file.
+///
+/// All settings are optional; unset settings fall back to the defaults
+/// documented for the corresponding command-line arguments.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Default for `--min-stale`, e.g. "2w".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_stale: Option<String>,
+    /// Default for `--max-depth`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_depth: Option<usize>,
+    /// Default for `--one-file-system`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub one_file_system: Option<bool>,
+    /// Default for `--follow-symlinks`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub follow_symlinks: Option<bool>,
+    /// Default for `--hidden`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hidden: Option<bool>,
+    /// Default for `--no-ignore`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_ignore: Option<bool>,
+    /// Directories to search if none are given on the command line.
+    pub directories: Vec<PathBuf>,
+    /// Default for `--type`.
+    pub types: Vec<String>,
+    /// Directories that should never be searched for projects.
+    ///
+    /// Patterns that contain no slash match directories of that name
+    /// anywhere; patterns starting with `/` or `~/` match absolute paths.
+    pub exclude: Vec<String>,
+    /// Probe plugins to use in addition to those found on `PATH`.
+    pub external_probes: Vec<PathBuf>,
+    /// Settings for `--archive`.
+    pub archive: ArchiveConfig,
+    /// Settings for `--stage`.
+    pub stage: StageConfig,
+    /// Settings for individual build tools, keyed by project type (as used
+    /// with `--type`).
+    pub tools: BTreeMap<String, ToolConfig>,
+    /// Additional build tools.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub custom_tools: Vec<CustomToolConfig>,
+    /// Pinned projects, in addition to those with a keep file.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub keep: Vec<Keep>,
+}
+
+/// Settings for `--archive`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArchiveConfig {
+    /// Whether to archive projects after cleaning them, as if `--archive`
+    /// was given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    /// The xz compression level (0-9).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compression_level: Option<u32>,
+}
+
+/// Settings for `--stage`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StageConfig {
+    /// Whether to stage directories instead of deleting them, as if
+    /// `--stage` was given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    /// Default for `--grace-period`, e.g. "1w".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grace_period: Option<String>,
+}
+
+/// Settings for a single build tool.
+///
+/// For now, a build tool can only be disabled; there are no per-tool
+/// overrides of the other settings.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ToolConfig {
+    /// Set to `false` to never consider projects of this type.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+}
+
+impl Config {
+    /// Loads and merges the user's and the local configuration file.
+    ///
+    /// If `user_config` is given, it is used instead of the user's
+    /// configuration file, which is then required to exist. The local
+    /// configuration file is looked up in `search_root`, the directory to
+    /// search for projects, and its parents.
+    pub fn load(user_config: Option<&Path>, search_root: &Path) -> anyhow::Result<Self> {
+        let user = match user_config {
+            Some(path) => Self::from_file(path)?,
+            None => match user_config_path() {
+                Some(path) if path.is_file() => Self::from_file(&path)?,
+                _ => Self::default(),
+            },
+        };
+
+        let search_root = search_root
+            .canonicalize()
+            .or_else(|_| env::current_dir().map(|dir| dir.join(search_root)));
+        let local = match search_root.ok().and_then(|dir| find_local_config(&dir)) {
+            Some(path) => Self::from_file(&path)?,
+            None => Self::default(),
+        };
+
+        Ok(user.merge(local))
+    }
+
+    /// Reads a single configuration file.
+    ///
+    /// Relative paths in `directories`, `external_probes` and `keep` are
+    /// resolved against the directory that contains the file.
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        debug!("reading configuration from {}", path.display());
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read configuration file {}", path.display()))?;
+        let mut config: Config = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse configuration file {}", path.display()))?;
+
+        let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+        config.directories = config
+            .directories
+            .iter()
+            .map(|dir| base_dir.join(expand_tilde(dir)))
+            .collect();
+        config.external_probes = config
+            .external_probes
+            .iter()
+            .map(|probe| base_dir.join(expand_tilde(probe)))
+            .collect();
+        for keep in config.keep.iter_mut() {
+            keep.path = keep.path.as_ref().map(|p| base_dir.join(expand_tilde(p)));
+        }
+
+        config
+            .validate()
+            .with_context(|| format!("Invalid configuration file {}", path.display()))?;
+        Ok(config)
+    }
+
+    /// Combines two configurations; settings in `other` take precedence.
+    ///
+    /// Exclusion patterns, probe plugins and pinned projects are accumulated
+    /// rather than replaced.
+    pub fn merge(mut self, other: Config) -> Self {
+        if other.min_stale.is_some() {
+            self.min_stale = other.min_stale;
+        }
+        if other.max_depth.is_some() {
+            self.max_depth = other.max_depth;
+        }
+        if other.one_file_system.is_some() {
+            self.one_file_system = other.one_file_system;
+        }
+        if other.follow_symlinks.is_some() {
+            self.follow_symlinks = other.follow_symlinks;
+        }
+        if other.hidden.is_some() {
+            self.hidden = other.hidden;
+        }
+        if other.no_ignore.is_some() {
+            self.no_ignore = other.no_ignore;
+        }
+        if !other.directories.is_empty() {
+            self.directories = other.directories;
+        }
+        if !other.types.is_empty() {
+            self.types = other.types;
+        }
+        self.exclude.extend(other.exclude);
+        for probe in other.external_probes {
+            if !self.external_probes.contains(&probe) {
+                self.external_probes.push(probe);
+            }
+        }
+        if other.archive.enabled.is_some() {
+            self.archive.enabled = other.archive.enabled;
+        }
+        if other.archive.compression_level.is_some() {
+            self.archive.compression_level = other.archive.compression_level;
+        }
+        if other.stage.enabled.is_some() {
+            self.stage.enabled = other.stage.enabled;
+        }
+        if other.stage.grace_period.is_some() {
+            self.stage.grace_period = other.stage.grace_period;
+        }
+        for (name, tool) in other.tools {
+            let entry = self.tools.entry(name).or_default();
+            if tool.enabled.is_some() {
+                entry.enabled = tool.enabled;
+            }
+        }
+        for custom_tool in other.custom_tools {
+            // A declaration with the same name replaces the existing one
+            self.custom_tools
+                .retain(|existing| !existing.name.eq_ignore_ascii_case(&custom_tool.name));
+            self.custom_tools.push(custom_tool);
+        }
+        for keep in other.keep {
+            // A pin for the same project replaces the existing one
+            self.keep.retain(|existing| existing.path != keep.path);
+            self.keep.push(keep);
+        }
+        self
+    }
+
+    /// Applies the command-line arguments on top of this configuration.
+    ///
+    /// The result is the effective configuration.
+    pub fn merge_cli(self, cli: &Cli) -> Self {
+        let from_cli = Config {
+            min_stale: cli.min_stale.map(format_duration),
+            max_depth: cli.max_depth,
+            one_file_system: flag(cli.one_file_system, cli.no_one_file_system),
+            follow_symlinks: flag(cli.follow_symlinks, cli.no_follow_symlinks),
+            hidden: flag(cli.hidden, cli.no_hidden),
+            no_ignore: cli.no_ignore.then(|| true),
+            directories: cli.directories.clone(),
+            types: cli.types.clone(),
+            exclude: cli.exclude.clone(),
+            external_probes: Vec::new(),
+            archive: ArchiveConfig {
+                enabled: flag(cli.archive, cli.no_archive),
+                compression_level: cli.compression_level,
+            },
+            stage: StageConfig {
+                enabled: flag(cli.stage, cli.no_stage),
+                grace_period: cli.grace_period.map(format_duration),
+            },
+            tools: BTreeMap::new(),
+            custom_tools: Vec::new(),
+            keep: Vec::new(),
+        };
+        self.merge(from_cli)
+    }
+
+    /// The configured default for `--min-stale`.
+    pub fn min_stale(&self) -> anyhow::Result<Option<Duration>> {
+        self.min_stale.as_deref().map(parse_duration).transpose()
+    }
+
+    /// The configured default for `--grace-period`.
+    pub fn grace_period(&self) -> anyhow::Result<Option<Duration>> {
+        self.stage
+            .grace_period
+            .as_deref()
+            .map(parse_duration)
+            .transpose()
+    }
+
+    /// Project types that have been disabled in the `tools` section.
+    pub fn disabled_types(&self) -> Vec<&str> {
+        self.tools
+            .iter()
+            .filter(|(_, tool)| tool.enabled == Some(false))
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    /// The exclusion patterns, compiled.
+    pub fn exclude(&self) -> anyhow::Result<GlobSet> {
+        exclude_globset(&self.exclude)
+    }
+
+    /// Renders the configuration in the configuration file format.
+    pub fn to_toml(&self) -> anyhow::Result<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        self.min_stale()?;
+        self.grace_period()?;
+        self.exclude()?;
+        for custom_tool in &self.custom_tools {
+            CustomProbe::try_from(custom_tool)
+                .with_context(|| format!("Invalid custom build tool {:?}", custom_tool.name))?;
+        }
+        for keep in &self.keep {
+            if keep.path.is_none() {
+                bail!("Every [[keep]] section requires a path");
+            }
+            keep.validate()?;
+        }
+        if let Some(level) = self.archive.compression_level {
+            if level > 9 {
+                bail!("archive.compression_level must be between 0 and 9, got {level}");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compiles exclusion patterns into a [`GlobSet`] that matches absolute paths.
+///
+/// Patterns that start with `/` or `~/` are anchored; all other patterns may
+/// match anywhere below the searched directories.
+pub fn exclude_globset(patterns: &[String]) -> anyhow::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let trimmed = pattern.trim_end_matches('/');
+        let anchored = if let Some(rest) = trimmed.strip_prefix("~/") {
+            match home_dir() {
+                Some(home) => format!("{}/{rest}", home.display()),
+                None => bail!("Cannot expand {pattern:?}: the home directory is unknown"),
+            }
+        } else if trimmed.starts_with('/') {
+            trimmed.to_owned()
+        } else {
+            format!("**/{trimmed}")
+        };
+        let glob = Glob::new(&anchored)
+            .with_context(|| format!("Invalid exclusion pattern {pattern:?}"))?;
+        builder.add(glob);
+    }
+    Ok(builder.build()?)
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{}d", duration.whole_days())
+}
+
+fn user_config_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| home_dir().map(|home| home.join(".config")))?;
+    Some(config_home.join("makeclean").join("config.toml"))
+}
+
+/// The setting of a flag that can be turned on and off on the command line,
+/// e.g. `--hidden` and `--no-hidden`.
+fn flag(on: bool, off: bool) -> Option<bool> {
+    if on {
+        Some(true)
+    } else if off {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+fn find_local_config(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(LOCAL_CONFIG_FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+pub(crate) fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+}
+
+fn expand_tilde(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn local_settings_take_precedence_but_exclusions_accumulate() {
+        let user: Config = toml::from_str(
+            r#"
+            min_stale = "1w"
+            types = ["cargo"]
+            exclude = ["vendor"]
+
+            [tools.npm]
+            enabled = false
+            "#,
+        )
+        .unwrap();
+        let local: Config = toml::from_str(
+            r#"
+            min_stale = "2m"
+            exclude = ["third_party"]
+
+            [tools.npm]
+            enabled = true
+            "#,
+        )
+        .unwrap();
+
+        let config = user.merge(local);
+
+        assert_eq!(config.min_stale.as_deref(), Some("2m"));
+        assert_eq!(config.types, vec!["cargo"]);
+        assert_eq!(config.exclude, vec!["vendor", "third_party"]);
+        assert_eq!(config.tools["npm"].enabled, Some(true));
+    }
+
+    #[test]
+    fn unanchored_exclusion_patterns_match_anywhere() {
+        let globset = exclude_globset(&["vendor/".to_owned(), "/abs/path".to_owned()]).unwrap();
+
+        assert!(globset.is_match(Path::new("/home/user/code/vendor")));
+        assert!(globset.is_match(Path::new("/abs/path")));
+        assert!(!globset.is_match(Path::new("/home/user/code/vendored")));
+        assert!(!globset.is_match(Path::new("/home/abs/path")));
+    }
+
+    #[test]
+    fn custom_tools_with_the_same_name_are_replaced() {
+        let user: Config = toml::from_str(
+            r#"
+            [[custom_tools]]
+            name = "bazel"
+            markers = ["WORKSPACE"]
+
+            [[custom_tools]]
+            name = "make"
+            markers = ["Makefile"]
+            "#,
+        )
+        .unwrap();
+        let local: Config = toml::from_str(
+            r#"
+            [[custom_tools]]
+            name = "Bazel"
+            markers = ["WORKSPACE.bazel"]
+            "#,
+        )
+        .unwrap();
+
+        let config = user.merge(local);
+
+        let names: Vec<_> = config.custom_tools.iter().map(|t| &t.name).collect();
+        assert_eq!(names, vec!["make", "Bazel"]);
+        assert!(config.validate().is_ok());
+    }
+}
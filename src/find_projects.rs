@@ -1,65 +1,263 @@
-use std::path::Path;
-
-use crate::{build_tool_manager::BuildToolManager, project::ProjectFilter, Project};
-
-use ignore::WalkBuilder;
-use tracing::warn;
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+use crate::{
+    build_tool_manager::BuildToolManager, fs::Hardlinks, index::ScanIndex, project::ProjectFilter,
+    Project,
+};
+
+use ignore::{WalkBuilder, WalkState};
+use tracing::{debug, trace, warn};
 
 /// An iterator over [`Project`]s in and below a given directory.
+///
+/// The directory tree is searched in parallel, which includes determining
+/// the projects' build status and modification time. Projects are returned
+/// ordered by their path.
+///
+/// The ephemeral directories of any project found (see
+/// [`BuildTool::ephemeral_dirs`](crate::build_tools::BuildTool::ephemeral_dirs))
+/// are not searched, as they typically contain dependencies rather than
+/// projects, and can be huge.
 pub fn projects_below<'a>(
     path: &Path,
     project_filter: &'a ProjectFilter,
     build_tool_manager: &'a BuildToolManager,
 ) -> impl Iterator<Item = Project> + 'a {
-    let path = path.canonicalize().expect("canonicalized path");
+    projects_below_with_index(
+        path,
+        project_filter,
+        build_tool_manager,
+        &ScanIndex::disabled(),
+        &Hardlinks::default(),
+    )
+}
+
+/// Like [`projects_below`], but returns the projects as soon as they're
+/// found, in no particular order.
+///
+/// The search runs on a background thread, which stops once the iterator is
+/// dropped.
+pub fn stream_projects_below(
+    path: &Path,
+    project_filter: Arc<ProjectFilter>,
+    build_tool_manager: Arc<BuildToolManager>,
+) -> impl Iterator<Item = Project> {
+    let path = path.to_owned();
+    let (sender, receiver) = mpsc::channel();
+    // `Sender` isn't `Sync` on all supported Rust versions
+    let sender = Mutex::new(sender);
+    thread::spawn(move || {
+        search(
+            &path,
+            &project_filter,
+            &build_tool_manager,
+            &ScanIndex::disabled(),
+            &Hardlinks::default(),
+            |project| sender.lock().expect("not poisoned").send(project).is_ok(),
+        )
+    });
+    receiver.into_iter()
+}
 
+/// Like [`projects_below`], but uses and updates the given [`ScanIndex`].
+///
+/// Files that are hard links to files in `hardlinks` are not considered
+/// freeable, so sharing `hardlinks` across searches counts each file once.
+pub fn projects_below_with_index<'a>(
+    path: &Path,
+    project_filter: &'a ProjectFilter,
+    build_tool_manager: &'a BuildToolManager,
+    index: &ScanIndex,
+    hardlinks: &Hardlinks,
+) -> impl Iterator<Item = Project> + 'a {
+    let projects: Mutex<Vec<Project>> = Mutex::default();
+    search(
+        path,
+        project_filter,
+        build_tool_manager,
+        index,
+        hardlinks,
+        |project| {
+            projects.lock().expect("not poisoned").push(project);
+            true
+        },
+    );
+
+    let mut projects = projects.into_inner().expect("not poisoned");
+    projects.sort_by(|a, b| a.path().cmp(b.path()));
+    projects.into_iter()
+}
+
+/// Searches for projects in parallel, passing each project found to `found`.
+///
+/// The search stops early if `found` returns false.
+fn search(
+    path: &Path,
+    project_filter: &ProjectFilter,
+    build_tool_manager: &BuildToolManager,
+    index: &ScanIndex,
+    hardlinks: &Hardlinks,
+    found: impl Fn(Project) -> bool + Sync,
+) {
+    let path = path.canonicalize().expect("canonicalized path");
+    let exclude = project_filter.exclude.clone();
+    debug!(
+        ?path,
+        max_depth = ?project_filter.max_depth,
+        one_file_system = project_filter.one_file_system,
+        follow_symlinks = project_filter.follow_symlinks,
+        hidden = project_filter.hidden,
+        no_ignore = project_filter.no_ignore,
+        "searching for projects"
+    );
+
+    // The ephemeral directories of the projects found so far. The walker
+    // visits a directory before it looks at its children, so a project's
+    // ephemeral directories are known by the time they would be searched.
+    let pruned: Arc<Mutex<HashSet<PathBuf>>> = Arc::default();
+    let pruned_by_walker = Arc::clone(&pruned);
+    // Dependency stores are skipped even if no project they belong to was
+    // found, e.g., a `node_modules` directory without a `package.json` next
+    // to it
+    let dependency_dirs: HashSet<OsString> = build_tool_manager
+        .dependency_dirs()
+        .into_iter()
+        .map(OsString::from)
+        .collect();
+
+    let respect_ignore_files = !project_filter.no_ignore;
     WalkBuilder::new(path)
-        .standard_filters(true)
+        .hidden(!project_filter.hidden)
+        .parents(respect_ignore_files)
+        .ignore(respect_ignore_files)
+        .git_ignore(respect_ignore_files)
+        .git_global(respect_ignore_files)
+        .git_exclude(respect_ignore_files)
         // skip ignored directories even outside Git repositories
         .require_git(false)
-        .build()
-        // ignore any errors
-        .filter_map(|result| result.ok())
-        // iterate on directory entries only
-        .filter(|entry| entry.path().is_dir())
-        // get rid of symlinks, double slashes, etc.
-        .filter_map(|entry| entry.path().canonicalize().ok())
-        // check if there is a project at this location
-        .filter_map(
-            |path| match Project::from_dir(&path, project_filter, build_tool_manager) {
-                Ok(maybe_project) => maybe_project,
-                Err(e) => {
-                    warn!("Failed to parse project at {}: {e}", path.display());
-                    None
+        .max_depth(project_filter.max_depth)
+        .same_file_system(project_filter.one_file_system)
+        .follow_links(project_filter.follow_symlinks)
+        // skip excluded and ephemeral directories, including their contents
+        .filter_entry(move |entry| {
+            if exclude.is_match(entry.path()) {
+                debug!(path = ?entry.path(), "skipping excluded directory");
+                return false;
+            }
+            if pruned_by_walker
+                .lock()
+                .expect("not poisoned")
+                .contains(entry.path())
+            {
+                trace!(path = ?entry.path(), "skipping ephemeral directory");
+                return false;
+            }
+            if entry.depth() > 0 && dependency_dirs.contains(entry.file_name()) {
+                trace!(path = ?entry.path(), "skipping dependency directory");
+                return false;
+            }
+            true
+        })
+        .build_parallel()
+        .run(|| {
+            let pruned = Arc::clone(&pruned);
+            let found = &found;
+            Box::new(move |result| {
+                // Errors within a project are reported by the project itself
+                let entry = match result {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        warn!("Failed to search for projects: {e}");
+                        return WalkState::Continue;
+                    }
+                };
+                // consider directory entries only; symlinks are resolved only
+                // if they're followed
+                if !entry.file_type().map_or(false, |ft| ft.is_dir()) {
+                    return WalkState::Continue;
                 }
-            },
-        )
+                // get rid of symlinks, double slashes, etc.
+                let path = match entry.path().canonicalize() {
+                    Ok(path) => path,
+                    Err(_) => return WalkState::Continue,
+                };
+
+                // check if there is a project at this location
+                let build_tools = build_tool_manager.probe(&path);
+                {
+                    let mut pruned = pruned.lock().expect("not poisoned");
+                    for dir in build_tools.iter().flat_map(|x| x.ephemeral_dirs()) {
+                        // The walker reports paths relative to where it started
+                        if let Ok(relative) = dir.strip_prefix(&path) {
+                            pruned.insert(entry.path().join(relative));
+                        }
+                    }
+                }
+
+                match Project::from_build_tools(
+                    &path,
+                    build_tools,
+                    project_filter,
+                    index,
+                    hardlinks,
+                ) {
+                    Ok(Some(project)) => {
+                        if !found(project) {
+                            return WalkState::Quit;
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => warn!("Failed to parse project at {}: {e}", path.display()),
+                }
+
+                WalkState::Continue
+            })
+        });
 }
 
 #[cfg(test)]
 mod test {
-    use std::{fmt::Display, fs::OpenOptions, path::Path};
+    use std::{
+        fmt::Display,
+        fs::OpenOptions,
+        path::{Path, PathBuf},
+        sync::Arc,
+    };
 
     use assert_fs::{
         fixture::{FileWriteStr, PathChild, PathCreateDir},
         TempDir,
     };
+    use globset::GlobSet;
     use time::Duration;
 
     use crate::{
         build_tool_manager::BuildToolManager,
-        build_tools::{BuildTool, BuildToolKind, BuildToolProbe},
-        project::{Project, ProjectFilter, StatusFilter},
+        build_tools::{BuildTool, BuildToolProbe, CleanStep, Removal},
+        config::exclude_globset,
+        project::{CategoryFilter, Project, ProjectFilter, StatusFilter},
     };
 
-    use super::projects_below;
+    use super::{projects_below, stream_projects_below};
 
     #[derive(Debug)]
-    struct TestTool;
+    struct TestTool {
+        dir: PathBuf,
+    }
     impl BuildTool for TestTool {
-        fn clean_project(&mut self, _: bool) -> anyhow::Result<()> {
+        fn clean_plan(&self, _: Removal) -> anyhow::Result<Vec<CleanStep>> {
             unimplemented!("not executed in these tests")
         }
+
+        fn ephemeral_dirs(&self) -> Vec<PathBuf> {
+            vec![self.dir.join("out")]
+        }
     }
     impl Display for TestTool {
         fn fmt(&self, _: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -72,14 +270,16 @@ mod test {
     impl BuildToolProbe for TestProbe {
         fn probe(&self, path: &Path) -> Option<Box<dyn BuildTool>> {
             if path.join("projectfile").exists() {
-                Some(Box::new(TestTool {}))
+                Some(Box::new(TestTool {
+                    dir: path.to_owned(),
+                }))
             } else {
                 None
             }
         }
 
-        fn applies_to(&self, _: BuildToolKind) -> bool {
-            unimplemented!("not executed in these tests")
+        fn id(&self) -> &str {
+            "test"
         }
     }
 
@@ -104,6 +304,14 @@ mod test {
         ProjectFilter {
             min_stale: Duration::ZERO,
             status: StatusFilter::Any,
+            exclude: GlobSet::empty(),
+            max_depth: None,
+            one_file_system: false,
+            follow_symlinks: false,
+            hidden: false,
+            no_ignore: false,
+            keep: Vec::new(),
+            categories: CategoryFilter::default(),
         }
     }
 
@@ -155,11 +363,60 @@ mod test {
 
         dbg!(&projects);
         assert_eq!(projects.len(), 2);
-        // we expect BFS ordering
+        // we expect the projects to be ordered by path
         assert_eq!(projects[0].path(), root_path);
         assert_eq!(projects[1].path(), subdir_path);
     }
 
+    #[test]
+    fn returns_projects_ordered_by_path() {
+        let root = TempDir::new().unwrap();
+        let root_path = root.path().canonicalize().unwrap();
+
+        let mut expected = Vec::new();
+        for name in ["c", "a", "b/nested", "b", "a/nested/deeper"] {
+            let dir = root.child(name);
+            dir.create_dir_all().unwrap();
+            let dir_path = dir.path().canonicalize().unwrap();
+            fake_project_at(&dir_path);
+            expected.push(dir_path);
+        }
+        expected.sort();
+
+        let projects: Vec<_> = projects_below(&root_path, &project_filter(), &build_tool_manager())
+            .map(|project| project.path().to_owned())
+            .collect();
+
+        assert_eq!(projects, expected);
+    }
+
+    #[test]
+    fn streams_projects_as_they_are_found() {
+        let root = TempDir::new().unwrap();
+        let root_path = root.path().canonicalize().unwrap();
+
+        let mut expected = Vec::new();
+        for name in ["c", "a", "b/nested"] {
+            let dir = root.child(name);
+            dir.create_dir_all().unwrap();
+            let dir_path = dir.path().canonicalize().unwrap();
+            fake_project_at(&dir_path);
+            expected.push(dir_path);
+        }
+        expected.sort();
+
+        let mut projects: Vec<_> = stream_projects_below(
+            &root_path,
+            Arc::new(project_filter()),
+            Arc::new(build_tool_manager()),
+        )
+        .map(|project| project.path().to_owned())
+        .collect();
+        projects.sort();
+
+        assert_eq!(projects, expected);
+    }
+
     #[test]
     fn skips_project_in_hidden_dir() {
         let root = TempDir::new().unwrap();
@@ -177,6 +434,28 @@ mod test {
         assert!(projects.is_empty());
     }
 
+    #[test]
+    fn finds_project_in_hidden_dir_if_asked_to() {
+        let root = TempDir::new().unwrap();
+        let root_path = root.path().canonicalize().unwrap();
+
+        let hidden_dir = root.child(".hidden-dir");
+        hidden_dir.create_dir_all().unwrap();
+        let hidden_dir_path = hidden_dir.path().canonicalize().unwrap();
+
+        fake_project_at(&hidden_dir_path);
+        let project_filter = ProjectFilter {
+            hidden: true,
+            ..project_filter()
+        };
+        let projects: Vec<Project> =
+            projects_below(&root_path, &project_filter, &build_tool_manager()).collect();
+
+        dbg!(&projects);
+        assert_eq!(projects.len(), 1);
+        assert_eq!(projects[0].path(), hidden_dir_path);
+    }
+
     #[test]
     fn skips_project_in_gitignored_dir_even_outside_git_repositories() {
         let root = TempDir::new().unwrap();
@@ -196,4 +475,110 @@ mod test {
         dbg!(&projects);
         assert!(projects.is_empty());
     }
+
+    #[test]
+    fn skips_project_in_excluded_dir() {
+        let root = TempDir::new().unwrap();
+        let root_path = root.path().canonicalize().unwrap();
+
+        let excluded_dir = root.child("vendor").child("dependency");
+        excluded_dir.create_dir_all().unwrap();
+        let excluded_dir_path = excluded_dir.path().canonicalize().unwrap();
+
+        fake_project_at(excluded_dir_path);
+        let project_filter = ProjectFilter {
+            exclude: exclude_globset(&["vendor".to_owned()]).unwrap(),
+            ..project_filter()
+        };
+        let projects: Vec<Project> =
+            projects_below(&root_path, &project_filter, &build_tool_manager()).collect();
+
+        dbg!(&projects);
+        assert!(projects.is_empty());
+    }
+
+    #[test]
+    fn skips_project_below_max_depth() {
+        let root = TempDir::new().unwrap();
+        let root_path = root.path().canonicalize().unwrap();
+
+        let shallow_dir = root.child("a");
+        shallow_dir.create_dir_all().unwrap();
+        let shallow_dir_path = shallow_dir.path().canonicalize().unwrap();
+        fake_project_at(&shallow_dir_path);
+
+        let deep_dir = root.child("a").child("b");
+        deep_dir.create_dir_all().unwrap();
+        fake_project_at(deep_dir.path());
+
+        let project_filter = ProjectFilter {
+            max_depth: Some(1),
+            ..project_filter()
+        };
+        let projects: Vec<Project> =
+            projects_below(&root_path, &project_filter, &build_tool_manager()).collect();
+
+        dbg!(&projects);
+        assert_eq!(projects.len(), 1);
+        assert_eq!(projects[0].path(), shallow_dir_path);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follows_symlinks_only_if_asked_to() {
+        let root = TempDir::new().unwrap();
+        let root_path = root.path().canonicalize().unwrap();
+        let elsewhere = TempDir::new().unwrap();
+        let elsewhere_path = elsewhere.path().canonicalize().unwrap();
+
+        fake_project_at(&elsewhere_path);
+        std::os::unix::fs::symlink(&elsewhere_path, root.child("link").path()).unwrap();
+
+        let projects: Vec<Project> =
+            projects_below(&root_path, &project_filter(), &build_tool_manager()).collect();
+        dbg!(&projects);
+        assert!(projects.is_empty());
+
+        let project_filter = ProjectFilter {
+            follow_symlinks: true,
+            ..project_filter()
+        };
+        let projects: Vec<Project> =
+            projects_below(&root_path, &project_filter, &build_tool_manager()).collect();
+        dbg!(&projects);
+        assert_eq!(projects.len(), 1);
+        assert_eq!(projects[0].path(), elsewhere_path);
+    }
+
+    #[test]
+    fn finds_project_in_gitignored_dir_if_asked_to_but_never_searches_ephemeral_dirs() {
+        let root = TempDir::new().unwrap();
+        let root_path = root.path().canonicalize().unwrap();
+
+        root.child(".gitignore")
+            .write_str("/ignored-dir/\nout/\n")
+            .unwrap();
+
+        let ignored_dir = root.child("ignored-dir");
+        ignored_dir.create_dir_all().unwrap();
+        let ignored_dir_path = ignored_dir.path().canonicalize().unwrap();
+        fake_project_at(&ignored_dir_path);
+
+        // A project within the ephemeral directory of another project, e.g.,
+        // a packaged dependency:
+        let ephemeral_dir = ignored_dir.child("out").child("dependency");
+        ephemeral_dir.create_dir_all().unwrap();
+        fake_project_at(ephemeral_dir.path());
+
+        let project_filter = ProjectFilter {
+            no_ignore: true,
+            ..project_filter()
+        };
+        let projects: Vec<Project> =
+            projects_below(&root_path, &project_filter, &build_tool_manager()).collect();
+
+        dbg!(&projects);
+        assert_eq!(projects.len(), 1);
+        assert_eq!(projects[0].path(), ignored_dir_path);
+    }
 }
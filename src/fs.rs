@@ -1,20 +1,263 @@
 //! Utility functions for file system handling.
 
-use std::path::Path;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{self, Metadata},
+    ops::AddAssign,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
 
+use anyhow::Context;
+
+use serde::{Deserialize, Serialize};
 use walkdir::WalkDir;
 
-pub(crate) fn dir_size(path: &Path) -> u64 {
-    if !path.is_dir() {
-        return 0;
+/// The space taken up by a number of files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiskUsage {
+    /// The sum of the file sizes, as reported by `ls` or `du --apparent-size`.
+    pub apparent_bytes: u64,
+    /// The blocks allocated for the files, as reported by `du`, except for
+    /// hard-linked files that are still linked from elsewhere, or that have
+    /// been counted elsewhere already. This is what deleting the files would
+    /// actually free up.
+    pub freeable_bytes: u64,
+    /// The number of files.
+    pub files: u64,
+}
+
+impl AddAssign for DiskUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.apparent_bytes += other.apparent_bytes;
+        self.freeable_bytes += other.freeable_bytes;
+        self.files += other.files;
+    }
+}
+
+/// Identifies a file independently of its path.
+type Inode = (u64, u64);
+
+/// Files with several hard links that have been counted already.
+///
+/// Package managers like pnpm hard-link the files of dependencies from a
+/// global store, so deleting them in one project frees up nothing as long as
+/// they are still linked from elsewhere. Such files are only freeable if all
+/// of their links are removed along with the project (see
+/// [`UsageCounter::finish_all`]). Sharing one `Hardlinks` across all projects
+/// of a run makes sure that files reached from several projects whose
+/// ephemeral directories overlap are counted only once.
+#[derive(Debug, Default)]
+pub struct Hardlinks {
+    counted: Mutex<HashSet<Inode>>,
+}
+
+impl Hardlinks {
+    /// Returns true if the file hasn't been counted before.
+    fn claim(&self, inode: Inode) -> bool {
+        self.counted.lock().expect("not poisoned").insert(inode)
     }
+}
+
+/// A file with several hard links, as found by a [`UsageCounter`].
+#[derive(Debug, Clone, Copy)]
+struct HardlinkedFile {
+    /// The number of links to the file.
+    nlink: u64,
+    /// How many of its links were found.
+    found: u64,
+    allocated: u64,
+}
+
+/// Adds up the [`DiskUsage`] of files one by one.
+#[derive(Debug, Default)]
+pub(crate) struct UsageCounter {
+    usage: DiskUsage,
+    /// Files with several hard links, which are only freeable if all of
+    /// their links are removed.
+    hardlinked: HashMap<Inode, HardlinkedFile>,
+}
+
+impl UsageCounter {
+    pub(crate) fn add(&mut self, metadata: &Metadata) {
+        self.usage.files += 1;
+        self.usage.apparent_bytes += metadata.len();
+        let allocated = allocated_bytes(metadata);
+        match hardlinked_inode(metadata) {
+            Some((inode, nlink)) => {
+                self.hardlinked
+                    .entry(inode)
+                    .or_insert(HardlinkedFile {
+                        nlink,
+                        found: 0,
+                        allocated,
+                    })
+                    .found += 1;
+            }
+            None => self.usage.freeable_bytes += allocated,
+        }
+    }
+
+    /// Finishes counting the files that are removed together.
+    ///
+    /// A file with several hard links is only freeable if all of its links
+    /// were found by the counters, and if it hasn't been counted for another
+    /// project already. It's counted for the first of the counters that
+    /// found it, in the order of their keys, so the result doesn't depend on
+    /// the order in which the files were found.
+    pub(crate) fn finish_all<K: Ord>(
+        counters: BTreeMap<K, UsageCounter>,
+        hardlinks: &Hardlinks,
+    ) -> BTreeMap<K, DiskUsage> {
+        let mut found: HashMap<Inode, u64> = HashMap::new();
+        for counter in counters.values() {
+            for (inode, file) in &counter.hardlinked {
+                *found.entry(*inode).or_default() += file.found;
+            }
+        }
+        counters
+            .into_iter()
+            .map(|(key, counter)| {
+                let mut usage = counter.usage;
+                for (inode, file) in counter.hardlinked {
+                    let is_freeable = found.remove(&inode).map_or(false, |n| n >= file.nlink);
+                    if is_freeable && hardlinks.claim(inode) {
+                        usage.freeable_bytes += file.allocated;
+                    }
+                }
+                (key, usage)
+            })
+            .collect()
+    }
+}
+
+#[cfg(unix)]
+fn allocated_bytes(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    // `st_blocks` is always in units of 512 bytes
+    metadata.blocks() * 512
+}
+
+#[cfg(not(unix))]
+fn allocated_bytes(metadata: &Metadata) -> u64 {
+    metadata.len()
+}
 
-    WalkDir::new(path)
+/// The inode and number of links of a file with several hard links.
+#[cfg(unix)]
+fn hardlinked_inode(metadata: &Metadata) -> Option<(Inode, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.nlink() > 1).then(|| ((metadata.dev(), metadata.ino()), metadata.nlink()))
+}
+
+#[cfg(not(unix))]
+fn hardlinked_inode(_metadata: &Metadata) -> Option<(Inode, u64)> {
+    None
+}
+
+/// Identifies the file system a file is on, if supported by the platform.
+#[cfg(unix)]
+pub(crate) fn device_id(metadata: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.dev())
+}
+
+/// Identifies the file system a file is on, if supported by the platform.
+#[cfg(not(unix))]
+pub(crate) fn device_id(_metadata: &Metadata) -> Option<u64> {
+    None
+}
+
+/// The first directory at or below `dir` that is on another file system
+/// than the directory containing `dir`, i.e., a mount point.
+///
+/// Symbolic links are not followed.
+pub(crate) fn find_mount_point(dir: &Path) -> Option<PathBuf> {
+    let device = device_id(&fs::metadata(dir.parent()?).ok()?)?;
+    WalkDir::new(dir)
         .into_iter()
-        .filter_map(|entry| entry.ok())
-        .filter_map(|entry| entry.metadata().ok())
-        .filter(|metadata| metadata.is_file())
-        .fold(0, |acc, m| acc + m.len())
+        .filter_entry(|entry| entry.file_type().is_dir())
+        .filter_map(Result::ok)
+        .find(|entry| {
+            entry
+                .metadata()
+                .map_or(false, |metadata| device_id(&metadata) != Some(device))
+        })
+        .map(|entry| entry.into_path())
+}
+
+/// Renames `path` to the first of `candidates` that doesn't exist yet.
+///
+/// Returns `None` if all of them exist. Renaming is atomic, but only works
+/// within the same file system.
+pub(crate) fn rename_to_available(
+    path: &Path,
+    candidates: impl IntoIterator<Item = PathBuf>,
+) -> anyhow::Result<Option<PathBuf>> {
+    match candidates.into_iter().find(|candidate| !candidate.exists()) {
+        Some(new_path) => {
+            fs::rename(path, &new_path)
+                .with_context(|| format!("Failed to rename {:?} to {:?}", path, new_path))?;
+            Ok(Some(new_path))
+        }
+        None => Ok(None),
+    }
+}
+
+#[cfg(unix)]
+pub(crate) mod unix {
+    use std::{
+        fs::{self, DirBuilder},
+        io,
+        os::unix::fs::{DirBuilderExt, MetadataExt},
+        path::{Path, PathBuf},
+    };
+
+    use anyhow::{bail, Context};
+
+    /// The device of `path`, or of its closest ancestor that exists.
+    pub(crate) fn device_of(path: &Path) -> anyhow::Result<u64> {
+        for dir in path.ancestors() {
+            if let Ok(metadata) = fs::metadata(dir) {
+                return Ok(metadata.dev());
+            }
+        }
+        bail!("Failed to determine the file system of {}", path.display())
+    }
+
+    /// The topmost directory above `path` that is still on `device`.
+    pub(crate) fn mount_point(path: &Path, device: u64) -> PathBuf {
+        let mut mount_point = path;
+        for dir in path.ancestors().skip(1) {
+            match fs::metadata(dir) {
+                Ok(metadata) if metadata.dev() == device => mount_point = dir,
+                _ => break,
+            }
+        }
+        mount_point.to_owned()
+    }
+
+    pub(crate) fn create_private_dir_all(dir: &Path) -> io::Result<()> {
+        DirBuilder::new().recursive(true).mode(0o700).create(dir)
+    }
+
+    /// Creates `dir` if necessary, and makes sure it's a directory owned by
+    /// the current user, as others could have created it first, e.g., at the
+    /// top of a shared file system.
+    pub(crate) fn create_owned_dir(dir: &Path) -> anyhow::Result<()> {
+        // SAFETY: getuid is always successful and has no side effects
+        let uid = unsafe { libc::getuid() };
+        create_private_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        let metadata = fs::symlink_metadata(dir)?;
+        if !metadata.is_dir() || metadata.uid() != uid {
+            bail!(
+                "Refusing to use {}, as it's not a directory owned by the current user",
+                dir.display()
+            );
+        }
+        Ok(())
+    }
 }
 
 #[allow(non_upper_case_globals)]
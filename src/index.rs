@@ -0,0 +1,359 @@
+//! Persistent scan index.
+//!
+//! Determining the size of a project's build directories and its modification
+//! time means looking at every file in it, which takes a while for large
+//! directory trees. The index remembers what was found for each project, so
+//! that subsequent runs can skip that work for projects that haven't changed.
+//!
+//! The index is stored at `~/.cache/makeclean/index.json` (respecting
+//! `$XDG_CACHE_HOME`). An entry is only used if the project's directories
+//! still have the same modification times and inodes as when the entry was
+//! recorded. Note that modifying a file in place doesn't change the
+//! modification time of its directory, so cached entries are only used for
+//! listing projects, while cleaning always looks at the actual files.
+
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap},
+    env, fs,
+    hash::{Hash, Hasher},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::UNIX_EPOCH,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tracing::{debug, warn};
+use walkdir::WalkDir;
+
+use crate::{build_tools::BuildStatus, config::home_dir, fs::DiskUsage};
+
+/// Entries from other versions are discarded, as the fingerprint might be
+/// computed differently.
+const INDEX_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// What is known about the projects found in previous runs.
+#[derive(Debug)]
+pub struct ScanIndex {
+    /// Where the index is stored; `None` if the index is disabled.
+    path: Option<PathBuf>,
+    /// Whether existing entries may be used, or only updated.
+    use_entries: bool,
+    entries: Mutex<BTreeMap<PathBuf, IndexEntry>>,
+}
+
+/// What is known about a single project.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexEntry {
+    /// Identifies the state of the project's directories.
+    pub fingerprint: u64,
+    /// The build tools, as displayed to the user.
+    pub build_tools: Vec<String>,
+    /// The status of each of the build tools, in the same order.
+    pub status: Vec<BuildStatus>,
+    /// The project's name.
+    pub name: String,
+    /// When the project was last modified, in RFC3339.
+    pub mtime: String,
+    /// The disk usage of each existing ephemeral directory.
+    #[serde(default)]
+    pub ephemeral: BTreeMap<PathBuf, DiskUsage>,
+    /// Git repositories within the project directory.
+    #[serde(default)]
+    pub nested_repositories: Vec<PathBuf>,
+    /// Files and directories that could not be read.
+    #[serde(default)]
+    pub errors: Vec<String>,
+    /// Ephemeral directories that are symbolic links.
+    #[serde(default)]
+    pub symlinked_ephemeral_dirs: Vec<PathBuf>,
+    /// Mount points at or within ephemeral directories.
+    #[serde(default)]
+    pub ephemeral_mount_points: Vec<PathBuf>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct IndexFile {
+    version: String,
+    projects: BTreeMap<PathBuf, IndexEntry>,
+}
+
+impl ScanIndex {
+    /// An index that neither remembers nor uses anything.
+    pub fn disabled() -> Self {
+        Self {
+            path: None,
+            use_entries: false,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// Loads the index from its default location.
+    ///
+    /// If `use_entries` is false, existing entries are not used, but still
+    /// updated with what is found. A missing or unreadable index is treated
+    /// as empty.
+    pub fn open(use_entries: bool) -> Self {
+        match index_path() {
+            Some(path) => Self::open_at(path, use_entries),
+            None => Self::disabled(),
+        }
+    }
+
+    /// Loads the index from the given file.
+    pub fn open_at(path: PathBuf, use_entries: bool) -> Self {
+        let entries = match read_index(&path) {
+            Ok(Some(index)) if index.version == INDEX_VERSION => index.projects,
+            Ok(Some(_)) => {
+                debug!("discarding scan index of a different version");
+                BTreeMap::new()
+            }
+            Ok(None) => BTreeMap::new(),
+            Err(e) => {
+                warn!("Ignoring scan index at {}: {e:#}", path.display());
+                BTreeMap::new()
+            }
+        };
+        Self {
+            path: Some(path),
+            use_entries,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    /// Whether the index is used at all.
+    pub fn is_enabled(&self) -> bool {
+        self.path.is_some()
+    }
+
+    /// Returns the entry for the project at `path`, if any, without checking
+    /// whether it's still valid.
+    ///
+    /// This allows to skip computing the fingerprint (see [`ScanIndex::get`])
+    /// for projects that have no entry.
+    pub fn candidate(&self, path: &Path) -> Option<IndexEntry> {
+        if !self.use_entries {
+            return None;
+        }
+        self.entries
+            .lock()
+            .expect("not poisoned")
+            .get(path)
+            .cloned()
+    }
+
+    /// Returns the entry for the project at `path`, if it's still valid.
+    pub fn get(&self, path: &Path, fingerprint: u64) -> Option<IndexEntry> {
+        let entry = self.candidate(path)?;
+        if entry.fingerprint == fingerprint {
+            debug!(?path, "using scan index entry");
+            Some(entry)
+        } else {
+            debug!(?path, "scan index entry is outdated");
+            None
+        }
+    }
+
+    /// Remembers what was found for the project at `path`.
+    pub fn insert(&self, path: &Path, entry: IndexEntry) {
+        if !self.is_enabled() {
+            return;
+        }
+        self.entries
+            .lock()
+            .expect("not poisoned")
+            .insert(path.to_owned(), entry);
+    }
+
+    /// Writes the index to disk, dropping entries of projects that no longer
+    /// exist.
+    pub fn save(&self) -> anyhow::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let mut projects = self.entries.lock().expect("not poisoned").clone();
+        projects.retain(|project_dir, _| project_dir.is_dir());
+        let index = IndexFile {
+            version: INDEX_VERSION.to_owned(),
+            projects,
+        };
+
+        let dir = path.parent().expect("index path has a parent");
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+        // Write to a temporary file first, so concurrent runs never see a
+        // partially written index
+        let tmp_path = path.with_extension(format!("json.{}", std::process::id()));
+        let mut file = fs::File::create(&tmp_path)
+            .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+        serde_json::to_writer(&mut file, &index)?;
+        file.flush()?;
+        fs::rename(&tmp_path, path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+}
+
+impl IndexEntry {
+    /// The project's modification time.
+    pub fn mtime(&self) -> Option<OffsetDateTime> {
+        OffsetDateTime::parse(&self.mtime, &Rfc3339).ok()
+    }
+}
+
+/// Directories of version control systems, which change with every commit
+/// or fetch but don't affect what is found.
+const VCS_DIRS: &[&str] = &[".git", ".hg", ".svn"];
+
+/// Identifies the state of the directories within `project_dir`, based on
+/// their modification times and inodes.
+///
+/// Adding, removing or renaming a file changes the modification time of its
+/// directory, so the fingerprint changes whenever files are created or
+/// deleted in the project. The `ephemeral_dirs` are not looked into, as they
+/// are typically large, so only adding or removing them, or entries at their
+/// top level, changes the fingerprint. Version control directories are
+/// skipped altogether.
+pub fn fingerprint(project_dir: &Path, ephemeral_dirs: &[PathBuf]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    let mut walker = WalkDir::new(project_dir).sort_by_file_name().into_iter();
+    while let Some(entry) = walker.next() {
+        let entry = match entry {
+            Ok(entry) if entry.file_type().is_dir() => entry,
+            _ => continue,
+        };
+        let is_vcs_dir =
+            entry.depth() > 0 && VCS_DIRS.iter().any(|name| entry.file_name() == *name);
+        if is_vcs_dir {
+            walker.skip_current_dir();
+            continue;
+        }
+        if ephemeral_dirs.iter().any(|dir| dir == entry.path()) {
+            walker.skip_current_dir();
+        }
+
+        entry.path().hash(&mut hasher);
+        if let Ok(metadata) = entry.metadata() {
+            if let Ok(mtime) = metadata.modified() {
+                mtime
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .hash(&mut hasher);
+            }
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::MetadataExt;
+                metadata.ino().hash(&mut hasher);
+            }
+        }
+    }
+    hasher.finish()
+}
+
+fn index_path() -> Option<PathBuf> {
+    let cache_home = env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| home_dir().map(|home| home.join(".cache")))?;
+    Some(cache_home.join("makeclean").join("index.json"))
+}
+
+fn read_index(path: &Path) -> anyhow::Result<Option<IndexFile>> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(path)?;
+    let index = serde_json::from_str(&content)?;
+    Ok(Some(index))
+}
+
+#[cfg(test)]
+mod test {
+    use assert_fs::{
+        fixture::{FileWriteStr, PathChild, PathCreateDir},
+        TempDir,
+    };
+
+    use super::*;
+
+    #[test]
+    fn fingerprint_changes_when_files_are_added_or_removed() {
+        let root = TempDir::new().unwrap();
+        root.child("src/main.rs").write_str("fn main() {}").unwrap();
+        let before = fingerprint(root.path(), &[]);
+        assert_eq!(fingerprint(root.path(), &[]), before);
+
+        root.child("target").create_dir_all().unwrap();
+        let with_target = fingerprint(root.path(), &[]);
+        assert_ne!(with_target, before);
+
+        // Files modified in place are not detected
+        root.child("src/main.rs")
+            .write_str("fn main() { println!() }")
+            .unwrap();
+        assert_eq!(fingerprint(root.path(), &[]), with_target);
+
+        fs::remove_dir(root.child("target").path()).unwrap();
+        assert_ne!(fingerprint(root.path(), &[]), with_target);
+    }
+
+    #[test]
+    fn fingerprint_doesnt_look_into_ephemeral_and_vcs_directories() {
+        let root = TempDir::new().unwrap();
+        let ephemeral_dirs = vec![root.child("target").to_path_buf()];
+        root.child("target/debug/app").write_str("").unwrap();
+        root.child(".git/objects/ab").create_dir_all().unwrap();
+        let before = fingerprint(root.path(), &ephemeral_dirs);
+
+        root.child("target/debug/deps/lib.rlib")
+            .write_str("")
+            .unwrap();
+        root.child(".git/objects/cd").create_dir_all().unwrap();
+        assert_eq!(fingerprint(root.path(), &ephemeral_dirs), before);
+
+        root.child("src").create_dir_all().unwrap();
+        assert_ne!(fingerprint(root.path(), &ephemeral_dirs), before);
+    }
+
+    #[test]
+    fn entries_survive_a_round_trip_and_are_validated() {
+        let cache_dir = TempDir::new().unwrap();
+        let project = TempDir::new().unwrap();
+        let index_path = cache_dir.child("index.json").to_path_buf();
+
+        let index = ScanIndex::open_at(index_path.clone(), true);
+        index.insert(
+            project.path(),
+            IndexEntry {
+                fingerprint: 1,
+                build_tools: vec!["Cargo".to_owned()],
+                status: vec![BuildStatus::Built {
+                    freeable_bytes: 42,
+                    apparent_bytes: 40,
+                }],
+                name: "project".to_owned(),
+                mtime: "2022-03-31T12:00:00Z".to_owned(),
+                ephemeral: BTreeMap::new(),
+                nested_repositories: Vec::new(),
+                errors: Vec::new(),
+                symlinked_ephemeral_dirs: Vec::new(),
+                ephemeral_mount_points: Vec::new(),
+            },
+        );
+        index.save().unwrap();
+
+        let index = ScanIndex::open_at(index_path.clone(), true);
+        assert!(index.get(project.path(), 2).is_none());
+        let entry = index.get(project.path(), 1).unwrap();
+        assert_eq!(entry.name, "project");
+        assert!(entry.mtime().is_some());
+
+        // Entries are only updated, but not used, if asked to:
+        let index = ScanIndex::open_at(index_path, false);
+        assert!(index.get(project.path(), 1).is_none());
+    }
+}
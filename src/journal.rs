@@ -0,0 +1,352 @@
+//! Journal of destructive actions, so they can be undone.
+//!
+//! Every file or directory removed while cleaning a project, and every
+//! project that is archived, is recorded in an append-only journal at
+//! `~/.local/state/makeclean/journal.jsonl` (respecting `$XDG_STATE_HOME`),
+//! one JSON object per line. All entries written by the same run share a
+//! run ID, which `makeclean undo` uses to restore what is still recoverable:
+//! directories that were moved to the trash or staged, and archived projects.
+
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use time::{
+    format_description::well_known::Rfc3339, macros::format_description, Duration, OffsetDateTime,
+};
+use tracing::warn;
+
+use crate::{
+    build_tools::{Removal, Removed},
+    config::home_dir,
+    project::archive::extract_tar_xz,
+    staging, trash,
+};
+
+/// The append-only journal, as written by a single run.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    run_id: String,
+    grace_period: Duration,
+}
+
+/// A single destructive action.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// Identifies the run that took the action.
+    pub run_id: String,
+    /// When the action was taken, in RFC3339.
+    pub timestamp: String,
+    /// The project directory.
+    pub project: PathBuf,
+    pub action: Action,
+    /// What was removed, or replaced by an archive.
+    pub path: PathBuf,
+    /// The disk space taken up by what was removed (see
+    /// [`crate::fs::DiskUsage::freeable_bytes`]), or the size of the archive.
+    pub bytes: u64,
+    /// Where the removed files are kept, if they can be restored.
+    pub backup: Option<PathBuf>,
+    /// When the grace period of a staged directory is over, in RFC3339.
+    #[serde(default)]
+    pub expires: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// Deleted for good.
+    Delete,
+    /// Moved to the trash, which is where `backup` points to.
+    Trash,
+    /// Moved to the staging area at `backup`, until its grace period is over.
+    Stage,
+    /// The project's files were replaced by the archive at `backup`.
+    Archive,
+}
+
+impl Journal {
+    /// Opens the journal at its default location for a new run.
+    pub fn open() -> anyhow::Result<Self> {
+        match journal_path() {
+            Some(path) => Self::open_at(path),
+            None => bail!("Failed to locate the journal: neither $XDG_STATE_HOME nor $HOME is set"),
+        }
+    }
+
+    /// Opens the journal at the given file for a new run.
+    ///
+    /// Fails if the file cannot be written to, so nothing is removed without
+    /// being recorded.
+    pub fn open_at(path: PathBuf) -> anyhow::Result<Self> {
+        let dir = path.parent().expect("journal path has a parent");
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open the journal at {}", path.display()))?;
+
+        let now = OffsetDateTime::now_utc()
+            .format(format_description!(
+                "[year][month][day]-[hour][minute][second]"
+            ))
+            .expect("can format the current time");
+        let run_id = format!("{now}-{}", std::process::id());
+        Ok(Self {
+            path,
+            run_id,
+            grace_period: staging::DEFAULT_GRACE_PERIOD,
+        })
+    }
+
+    /// Sets how long directories staged by this run are kept.
+    pub fn with_grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = grace_period;
+        self
+    }
+
+    /// Identifies the current run.
+    pub fn run_id(&self) -> &str {
+        &self.run_id
+    }
+
+    /// Records that a file or directory of `project` was removed.
+    pub fn record_removed(
+        &self,
+        project: &Path,
+        removed: &Removed,
+        bytes: u64,
+    ) -> anyhow::Result<()> {
+        let action = match (removed.removal, &removed.backup) {
+            (Removal::Trash, Some(_)) => Action::Trash,
+            (Removal::Stage, Some(_)) => Action::Stage,
+            _ => Action::Delete,
+        };
+        self.append(
+            project,
+            action,
+            &removed.path,
+            bytes,
+            removed.backup.clone(),
+        )
+    }
+
+    /// Records that the files of `project` were replaced by `archive`.
+    pub fn record_archived(&self, project: &Path, archive: &Path) -> anyhow::Result<()> {
+        let bytes = fs::metadata(archive).map_or(0, |metadata| metadata.len());
+        self.append(
+            project,
+            Action::Archive,
+            project,
+            bytes,
+            Some(archive.to_owned()),
+        )
+    }
+
+    fn append(
+        &self,
+        project: &Path,
+        action: Action,
+        path: &Path,
+        bytes: u64,
+        backup: Option<PathBuf>,
+    ) -> anyhow::Result<()> {
+        let now = OffsetDateTime::now_utc();
+        let expires = (action == Action::Stage).then(|| {
+            (now + self.grace_period)
+                .format(&Rfc3339)
+                .expect("can format the expiry time")
+        });
+        let entry = JournalEntry {
+            run_id: self.run_id.clone(),
+            timestamp: now.format(&Rfc3339).expect("can format the current time"),
+            project: project.to_owned(),
+            action,
+            path: path.to_owned(),
+            bytes,
+            backup,
+            expires,
+        };
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+
+        // A single write per line, so concurrent runs don't interleave
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open the journal at {}", self.path.display()))?;
+        file.write_all(line.as_bytes())
+            .with_context(|| format!("Failed to write to the journal at {}", self.path.display()))
+    }
+}
+
+impl JournalEntry {
+    /// When the action was taken.
+    pub fn timestamp(&self) -> Option<OffsetDateTime> {
+        OffsetDateTime::parse(&self.timestamp, &Rfc3339).ok()
+    }
+
+    /// When the grace period of a staged directory is over.
+    ///
+    /// Entries written before the expiry was recorded expire after the
+    /// default grace period.
+    pub fn expires(&self) -> Option<OffsetDateTime> {
+        match &self.expires {
+            Some(expires) => OffsetDateTime::parse(expires, &Rfc3339).ok(),
+            None => self
+                .timestamp()
+                .map(|timestamp| timestamp + staging::DEFAULT_GRACE_PERIOD),
+        }
+    }
+
+    /// Whether what was removed can still be restored.
+    pub fn is_recoverable(&self) -> bool {
+        self.action != Action::Delete && self.backup.as_ref().map_or(false, |path| path.exists())
+    }
+
+    /// Restores what was removed.
+    ///
+    /// Nothing is overwritten: restoring fails if something was created at
+    /// the original location in the meantime.
+    pub fn restore(&self) -> anyhow::Result<()> {
+        let backup = match (self.action, &self.backup) {
+            (Action::Delete, _) | (_, None) => {
+                bail!("it was deleted for good")
+            }
+            (_, Some(backup)) => backup,
+        };
+        match self.action {
+            Action::Delete => unreachable!("handled above"),
+            Action::Trash => trash::restore(backup, &self.path),
+            Action::Stage => staging::restore(backup, &self.path),
+            Action::Archive => restore_archive(backup, &self.path),
+        }
+    }
+}
+
+/// Reads all entries of the journal at its default location.
+///
+/// A missing journal has no entries. Lines that cannot be parsed are
+/// skipped.
+pub fn read_entries() -> anyhow::Result<Vec<JournalEntry>> {
+    match journal_path() {
+        Some(path) => read_entries_at(&path),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Like [`read_entries`], but reads the journal at the given file.
+pub fn read_entries_at(path: &Path) -> anyhow::Result<Vec<JournalEntry>> {
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read the journal at {}", path.display()))?;
+    let entries = content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                warn!("Skipping invalid journal entry: {e}");
+                None
+            }
+        })
+        .collect();
+    Ok(entries)
+}
+
+/// The most recent run of which something can still be restored.
+pub fn last_recoverable_run(entries: &[JournalEntry]) -> Option<&str> {
+    entries
+        .iter()
+        .rev()
+        .find(|entry| entry.is_recoverable())
+        .map(|entry| entry.run_id.as_str())
+}
+
+fn restore_archive(archive: &Path, project_dir: &Path) -> anyhow::Result<()> {
+    if !archive.is_file() {
+        bail!("{} no longer exists", archive.display());
+    }
+    // Other files would be overwritten or mixed up with the restored ones
+    for entry in fs::read_dir(project_dir)? {
+        let path = entry?.path();
+        if path != archive {
+            bail!(
+                "{} has been modified since it was archived, e.g., {} was added",
+                project_dir.display(),
+                path.display()
+            );
+        }
+    }
+    extract_tar_xz(archive, project_dir)
+        .with_context(|| format!("Failed to extract {}", archive.display()))?;
+    fs::remove_file(archive).with_context(|| format!("Failed to remove {}", archive.display()))
+}
+
+fn journal_path() -> Option<PathBuf> {
+    let state_home = env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| home_dir().map(|home| home.join(".local").join("state")))?;
+    Some(state_home.join("makeclean").join("journal.jsonl"))
+}
+
+#[cfg(test)]
+mod test {
+    use assert_fs::{
+        fixture::{FileWriteStr, PathChild},
+        TempDir,
+    };
+
+    use super::*;
+
+    #[test]
+    fn only_runs_with_existing_backups_can_be_undone() {
+        let state_home = TempDir::new().unwrap();
+        let project = TempDir::new().unwrap();
+        let journal_path = state_home.child("makeclean/journal.jsonl").to_path_buf();
+
+        let journal = Journal::open_at(journal_path.clone()).unwrap();
+        journal
+            .record_removed(
+                project.path(),
+                &Removed {
+                    path: project.child("target").to_path_buf(),
+                    removal: Removal::Delete,
+                    backup: None,
+                },
+                42,
+            )
+            .unwrap();
+        let entries = read_entries_at(&journal_path).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].action, Action::Delete);
+        assert_eq!(entries[0].bytes, 42);
+        assert!(last_recoverable_run(&entries).is_none());
+        assert!(entries[0].restore().is_err());
+
+        project.child("project.tar.xz").write_str("").unwrap();
+        journal
+            .record_archived(project.path(), project.child("project.tar.xz").path())
+            .unwrap();
+        let entries = read_entries_at(&journal_path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(last_recoverable_run(&entries), Some(journal.run_id()));
+
+        // Archives are not extracted over files created in the meantime
+        project.child("Cargo.toml").write_str("").unwrap();
+        assert!(entries[1].restore().is_err());
+        assert!(project.child("project.tar.xz").exists());
+    }
+}
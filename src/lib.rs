@@ -1,9 +1,15 @@
 pub mod build_tool_manager;
 pub mod build_tools;
 mod cli;
+pub mod config;
 pub mod find_projects;
-mod fs;
+pub mod fs;
+pub mod index;
+pub mod journal;
+pub mod processes;
 pub mod project;
+pub mod staging;
+pub mod trash;
 
 use anyhow::Context;
 use build_tool_manager::BuildToolManager;
@@ -19,14 +25,24 @@ use std::{
     io,
     path::{Path, PathBuf},
 };
+use tabular::{Row, Table};
 use time::Duration;
-use tracing::debug;
+use tracing::{debug, warn};
 
-pub use crate::cli::Cli;
+pub use crate::cli::{Cli, Command, RestoreArgs, UndoArgs};
 use crate::{
-    find_projects::projects_below,
-    fs::format_size,
-    project::{dto::ProjectDto, mtime::HumanReadableElapsed, ProjectFilter, StatusFilter},
+    build_tools::{Kept, Removal, Remover},
+    config::exclude_globset,
+    find_projects::projects_below_with_index,
+    fs::{format_size, Hardlinks},
+    index::ScanIndex,
+    journal::{Action, Journal, JournalEntry},
+    processes::{ProcessUse, ProcessUses},
+    project::{
+        dto::{BuildToolProbeDto, ProjectDto},
+        mtime::HumanReadableElapsed,
+        CategoryFilter, ProjectFilter, StatusFilter,
+    },
 };
 
 /// Prints projects to stdout.
@@ -34,29 +50,48 @@ pub fn list(cli: Cli, build_tool_manager: BuildToolManager) -> anyhow::Result<()
     let project_filter = {
         let min_stale = cli.min_stale.unwrap_or(Duration::ZERO);
         let status = StatusFilter::Any;
-        ProjectFilter { min_stale, status }
+        let exclude = exclude_globset(&cli.exclude)?;
+        let keep = cli.keep.clone();
+        ProjectFilter {
+            min_stale,
+            status,
+            exclude,
+            max_depth: cli.max_depth,
+            one_file_system: cli.one_file_system,
+            follow_symlinks: cli.follow_symlinks,
+            hidden: cli.hidden,
+            no_ignore: cli.no_ignore,
+            keep,
+            categories: CategoryFilter {
+                only: cli.only.clone(),
+                except: cli.except.clone(),
+            },
+        }
     };
     debug!("listing projects with {project_filter:?}");
 
+    let index = open_index(&cli);
+    let hardlinks = Hardlinks::default();
+
     // We use a Set as directories could overlap, and we don't want to print projects multiple times
     let mut printed_paths: HashSet<PathBuf> = HashSet::new();
     let mut freeable_bytes = 0;
     for directory in cli.directories {
-        for project in projects_below(&directory, &project_filter, &build_tool_manager) {
+        for project in projects_below_with_index(
+            &directory,
+            &project_filter,
+            &build_tool_manager,
+            &index,
+            &hardlinks,
+        ) {
             let is_new = printed_paths.insert(project.path().to_owned());
             if is_new {
                 print_project(&project, cli.json)?;
-                freeable_bytes += project
-                    .build_tools()
-                    .iter()
-                    .map(|x| match x.status() {
-                        Ok(build_tools::BuildStatus::Built { freeable_bytes }) => freeable_bytes,
-                        _ => 0,
-                    })
-                    .sum::<u64>();
+                freeable_bytes += project.freeable_bytes();
             }
         }
     }
+    save_index(&index);
 
     if !cli.json {
         println!();
@@ -74,6 +109,38 @@ pub fn list(cli: Cli, build_tool_manager: BuildToolManager) -> anyhow::Result<()
     Ok(())
 }
 
+/// Prints the known project types to stdout.
+pub fn list_types(cli: Cli, build_tool_manager: BuildToolManager) -> anyhow::Result<()> {
+    if cli.json {
+        for probe in build_tool_manager.probes() {
+            let dto = BuildToolProbeDto::from(probe.as_ref());
+            serde_json::to_writer(io::stdout(), &dto)?;
+            // Add the newline:
+            println!();
+        }
+        return Ok(());
+    }
+
+    let mut table = Table::new("{:<}  {:<}  {:<}");
+    table.add_row(
+        Row::new()
+            .with_cell("TYPE")
+            .with_cell("ALIASES")
+            .with_cell("CLEANS"),
+    );
+    for probe in build_tool_manager.probes() {
+        table.add_row(
+            Row::new()
+                .with_cell(probe.id())
+                .with_cell(probe.aliases().join(", "))
+                .with_cell(probe.ephemeral_dirs().join(", ")),
+        );
+    }
+    print!("{table}");
+
+    Ok(())
+}
+
 /// Removes generated and downloaded files from code projects to free up space.
 ///
 /// Runs in interactive mode unless either one of `cli.dry_run` and `cli.yes` is true.
@@ -97,41 +164,137 @@ pub fn clean(cli: Cli, build_tool_manager: BuildToolManager) -> anyhow::Result<(
         } else {
             StatusFilter::ExceptClean
         };
-        ProjectFilter { min_stale, status }
+        let exclude = exclude_globset(&cli.exclude)?;
+        let keep = cli.keep.clone();
+        ProjectFilter {
+            min_stale,
+            status,
+            exclude,
+            max_depth: cli.max_depth,
+            one_file_system: cli.one_file_system,
+            follow_symlinks: cli.follow_symlinks,
+            hidden: cli.hidden,
+            no_ignore: cli.no_ignore,
+            keep,
+            categories: CategoryFilter {
+                only: cli.only.clone(),
+                except: cli.except.clone(),
+            },
+        }
     };
 
+    // Directories staged by previous runs are deleted once their grace period
+    // is over, but only when staging and only once the user has confirmed.
+    // The journal is read now, so what this run stages isn't considered.
+    let staged_entries = if cli.stage && !cli.dry_run {
+        journal::read_entries()?
+    } else {
+        Vec::new()
+    };
+
+    // Cleaning removes files, so it's based on the actual files rather than on
+    // what the index remembers; the index isn't updated either, as that would
+    // mean fingerprinting every project
+    let index = ScanIndex::disabled();
+    let hardlinks = Hardlinks::default();
+
     // We use a HashMap as directories could overlap, and archiving a directory twice doesn't work
     let mut projects: HashMap<PathBuf, Project> = HashMap::new();
-    for directory in cli.directories {
-        for project in projects_below(&directory, &project_filter, &build_tool_manager) {
+    for directory in &cli.directories {
+        for project in projects_below_with_index(
+            directory,
+            &project_filter,
+            &build_tool_manager,
+            &index,
+            &hardlinks,
+        ) {
             if let Entry::Vacant(entry) = projects.entry(project.path().to_owned()) {
-                print_project(&project, cli.json)?;
+                // With --json --dry-run, projects are printed along with their
+                // clean plan, once it's known; projects that are pinned
+                // entirely aren't cleaned, so they're not offered for cleaning
+                // but reported as kept
+                let is_offered = !project.is_pinned_entirely() || cli.json;
+                if !(cli.json && cli.dry_run) && is_offered {
+                    print_project(&project, cli.json)?;
+                }
                 entry.insert(project);
             }
         }
     }
 
-    if cli.json && cli.dry_run {
-        // If we'd continue, we'd fck up the JSON output, as the dry-run output
-        // is not formatted.
-        return Ok(());
+    // Projects that are pinned entirely are left alone; those pinned with the
+    // "dependencies" scope are still cleaned partially
+    let pinned_paths: Vec<PathBuf> = projects
+        .iter()
+        .filter(|(_, project)| project.is_pinned_entirely())
+        .map(|(path, _)| path.clone())
+        .collect();
+    let mut pinned_projects: Vec<Project> = pinned_paths
+        .iter()
+        .map(|path| projects.remove(path).expect("must be there"))
+        .collect();
+    pinned_projects.sort_by(|a, b| a.path().cmp(b.path()));
+
+    // What couldn't be read might have been modified recently, or might not
+    // be removable, so such projects are only cleaned if forced to
+    let mut unreadable_projects: Vec<Project> = Vec::new();
+    if !cli.force {
+        let unreadable_paths: Vec<PathBuf> = projects
+            .iter()
+            .filter(|(_, project)| !project.errors().is_empty())
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in unreadable_paths {
+            unreadable_projects.push(projects.remove(&path).expect("must be there"));
+        }
     }
 
-    let freeable_bytes = projects
-        .values()
-        .flat_map(|p| p.build_tools().iter())
-        .map(|bt| match bt.status() {
-            Ok(build_tools::BuildStatus::Built { freeable_bytes }) => freeable_bytes,
-            _ => 0,
-        })
-        .sum::<u64>();
+    // Removing build outputs or dependencies while a build, a development
+    // server or an editor uses them breaks those, so such projects are only
+    // cleaned if forced to
+    let mut projects_in_use: Vec<(Project, ProcessUse)> = Vec::new();
+    if !cli.force && !projects.is_empty() {
+        let process_uses = ProcessUses::scan();
+        let paths_in_use: Vec<(PathBuf, ProcessUse)> = projects
+            .keys()
+            .filter_map(|path| Some((path.clone(), process_uses.user_of(path)?.clone())))
+            .collect();
+        for (path, process) in paths_in_use {
+            projects_in_use.push((projects.remove(&path).expect("must be there"), process));
+        }
+    }
+
+    // Dependencies that can't be restored exactly must not be removed without
+    // confirmation, which is asked for after the prompt below; with --yes,
+    // there's nobody to ask
+    let mut unreproducible_projects: Vec<Project> = Vec::new();
+    if cli.yes && !cli.force && !cli.dry_run {
+        unreproducible_projects = take_unreproducible_projects(&mut projects, |_, _| Ok(false))?;
+    }
+
+    let mut freeable_bytes = projects.values().map(|p| p.freeable_bytes()).sum::<u64>();
+
+    // Everything that is removed or archived is recorded, so it can be undone
+    let journal = if cli.dry_run || projects.is_empty() {
+        None
+    } else {
+        let grace_period = cli.grace_period.unwrap_or(staging::DEFAULT_GRACE_PERIOD);
+        Some(Journal::open()?.with_grace_period(grace_period))
+    };
+    let mut is_undoable = false;
+    let mut kept: Vec<Kept> = Vec::new();
+    // What was actually removed, which is less than expected if anything was
+    // kept
+    let mut removed_bytes: u64 = 0;
 
     let has_cleaned = {
         if projects.is_empty() {
             false
         } else {
             let do_continue = if cli.dry_run {
-                println!("\n{}", style("WOULD DO:").bold());
+                if !cli.json {
+                    println!("\n{}", style("WOULD DO:").bold());
+                }
                 true
             } else if cli.yes {
                 true
@@ -146,12 +309,79 @@ pub fn clean(cli: Cli, build_tool_manager: BuildToolManager) -> anyhow::Result<(
                     .interact()?
             };
 
-            if do_continue {
+            if do_continue && !cli.yes && !cli.force && !cli.dry_run {
+                let theme = theme();
+                unreproducible_projects = take_unreproducible_projects(
+                    &mut projects,
+                    |project, risks| {
+                        let prompt = format!(
+                            "The dependencies of {} might not be restored exactly ({}). Clean it anyway?",
+                            project.path().display(),
+                            risks.join("; ")
+                        );
+                        Ok(Confirm::with_theme(&*theme)
+                            .with_prompt(prompt)
+                            .default(false)
+                            .interact()?)
+                    },
+                )?;
+                freeable_bytes -= unreproducible_projects
+                    .iter()
+                    .map(|p| p.freeable_bytes())
+                    .sum::<u64>();
+            }
+
+            if do_continue && cli.dry_run {
+                let removal = removal_for(&cli);
+                let mut paths: Vec<&PathBuf> = projects.keys().collect();
+                paths.sort();
+                for path in paths {
+                    let project = &projects[path];
+                    let plan = project
+                        .clean_plan(removal)
+                        .with_context(|| format!("Failed to plan cleaning project {project}"))?;
+                    if cli.json {
+                        let dto = ProjectDto {
+                            clean_plan: Some(plan),
+                            ..ProjectDto::from(project)
+                        };
+                        serde_json::to_writer(io::stdout(), &dto)?;
+                        println!();
+                    } else {
+                        for step in plan {
+                            println!("{step}");
+                        }
+                        if cli.archive && project.pinned().is_none() {
+                            println!(
+                                "Would replace '{}/*' with {:?}",
+                                project.path().display(),
+                                project.archive_path()
+                            );
+                        }
+                    }
+                }
+                false
+            } else if do_continue {
+                let remover = Remover::new(removal_for(&cli));
+
                 // First clean all of them
                 for project in projects.values_mut() {
-                    project
-                        .clean(cli.dry_run)
-                        .with_context(|| format!("Failed to clean project {project}"))?;
+                    let result = project.clean(&remover);
+                    // Whatever was removed is recorded, even if cleaning failed halfway
+                    for removed in remover.take_removed() {
+                        let bytes = project
+                            .ephemeral_paths()
+                            .iter()
+                            .find(|path| path.path == removed.path)
+                            .map_or(0, |path| path.usage.freeable_bytes);
+                        removed_bytes += bytes;
+                        if let Some(journal) = &journal {
+                            journal.record_removed(project.path(), &removed, bytes)?;
+                            is_undoable |= removed.backup.is_some();
+                        }
+                    }
+                    kept.extend(remover.take_kept());
+                    result.with_context(|| format!("Failed to clean project {project}"))?;
                 }
 
                 if cli.archive {
@@ -160,8 +390,12 @@ pub fn clean(cli: Cli, build_tool_manager: BuildToolManager) -> anyhow::Result<(
                     // Projects are only archived if they're not part of another
                     // project that is also archived. In other words: we don't
                     // want nested tar.xz files.
+                    //
+                    // Pinned projects are never archived.
                     let projects_to_archive: Vec<PathBuf> = projects
-                        .keys()
+                        .iter()
+                        .filter(|(_, project)| project.pinned().is_none())
+                        .map(|(path, _)| path)
                         .filter(|path| {
                             // A project is part of another considered project, if
                             // its path is part of another path in projects.keys().
@@ -173,15 +407,21 @@ pub fn clean(cli: Cli, build_tool_manager: BuildToolManager) -> anyhow::Result<(
                         .cloned()
                         .collect();
 
+                    let compression_level = cli.compression_level.unwrap_or(6);
                     for path in projects_to_archive {
                         let project = projects.get_mut(&path).expect("must be there");
-                        project.archive(cli.dry_run).with_context(|| {
-                            format!("Failed to archive cleaned project {project}")
-                        })?;
+                        let archive_path =
+                            project.archive(compression_level).with_context(|| {
+                                format!("Failed to archive cleaned project {project}")
+                            })?;
+                        if let Some(journal) = &journal {
+                            journal.record_archived(project.path(), &archive_path)?;
+                            is_undoable = true;
+                        }
                     }
                 }
 
-                !cli.dry_run
+                true
             } else {
                 println!("No changes made.");
                 false
@@ -189,6 +429,30 @@ pub fn clean(cli: Cli, build_tool_manager: BuildToolManager) -> anyhow::Result<(
         }
     };
 
+    if cli.json && cli.dry_run {
+        // The projects that are not cleaned are listed without a plan
+        let mut excluded: Vec<&Project> = unreadable_projects
+            .iter()
+            .chain(projects_in_use.iter().map(|(project, _)| project))
+            .chain(unreproducible_projects.iter())
+            .chain(pinned_projects.iter())
+            .collect();
+        excluded.sort_by(|a, b| a.path().cmp(b.path()));
+        for project in excluded {
+            print_project(project, true)?;
+        }
+    }
+
+    // Without projects to clean, there's nothing to confirm
+    let purged_bytes = if has_cleaned || projects.is_empty() {
+        staging::purge_expired(&staged_entries)?
+            .iter()
+            .map(|entry| entry.bytes)
+            .sum::<u64>()
+    } else {
+        0
+    };
+
     if !cli.json {
         println!();
         println!("{}", style("SUMMARY:").bold());
@@ -199,11 +463,23 @@ pub fn clean(cli: Cli, build_tool_manager: BuildToolManager) -> anyhow::Result<(
         };
         println!(
             "  {}",
-            style(if has_cleaned {
+            style(if has_cleaned && cli.stage {
+                format!(
+                    "{} {projects_label} cleaned, staging approx. {} of build artifacts and dependencies for deletion (pending until the grace period is over; use `makeclean restore` to restore a project).",
+                    projects.len(),
+                    format_size(removed_bytes)
+                )
+            } else if has_cleaned && cli.trash {
+                format!(
+                    "{} {projects_label} cleaned, moving approx. {} of build artifacts and dependencies to the trash (pending until the trash is emptied).",
+                    projects.len(),
+                    format_size(removed_bytes)
+                )
+            } else if has_cleaned {
                 format!(
                     "{} {projects_label} cleaned, which freed approx. {} of build artifacts and dependencies.",
                     projects.len(),
-                    format_size(freeable_bytes)
+                    format_size(removed_bytes)
                 )
             } else {
                 format!(
@@ -214,6 +490,26 @@ pub fn clean(cli: Cli, build_tool_manager: BuildToolManager) -> anyhow::Result<(
             })
             .green()
         );
+        if purged_bytes > 0 {
+            println!(
+                "  {}",
+                style(format!(
+                    "Deleted approx. {} of directories staged by previous runs, as their grace period is over.",
+                    format_size(purged_bytes)
+                ))
+                .green()
+            );
+        }
+        if let Some(journal) = journal.as_ref().filter(|_| is_undoable) {
+            println!(
+                "  {}",
+                style(format!(
+                    "To restore what was moved to the trash, staged or archived, run `makeclean undo {}`.",
+                    journal.run_id()
+                ))
+                .dim()
+            );
+        }
         let n_projects_without_vcs = projects.values().filter(|p| p.vcs().is_none()).count();
         if n_projects_without_vcs > 0 {
             println!(
@@ -229,11 +525,216 @@ pub fn clean(cli: Cli, build_tool_manager: BuildToolManager) -> anyhow::Result<(
                 .filter(|p| p.vcs().is_none())
                 .for_each(|p| println!("    {}", style(p.path().display()).dim()));
         }
+        if !unreproducible_projects.is_empty() {
+            println!(
+                "  {}",
+                style(format!(
+                    "{} projects not cleaned, as their dependencies might not be restored exactly (use --force to clean them anyway):",
+                    unreproducible_projects.len()
+                ))
+                .yellow()
+            );
+            unreproducible_projects.iter().for_each(|p| {
+                println!(
+                    "    {} ({})",
+                    style(p.path().display()).dim(),
+                    p.reproducibility_risks().join("; ")
+                )
+            });
+        }
+        if !projects_in_use.is_empty() {
+            println!(
+                "  {}",
+                style(format!(
+                    "{} projects not cleaned, as they are in use by running processes (use --force to clean them anyway):",
+                    projects_in_use.len()
+                ))
+                .yellow()
+            );
+            projects_in_use.iter().for_each(|(p, process)| {
+                println!(
+                    "    {} ({} (pid {}) uses {})",
+                    style(p.path().display()).dim(),
+                    process.name,
+                    process.pid,
+                    process.path.display()
+                )
+            });
+        }
+        if !pinned_projects.is_empty() {
+            println!(
+                "  {}",
+                style(format!("{} pinned projects kept:", pinned_projects.len())).yellow()
+            );
+            pinned_projects
+                .iter()
+                .filter_map(|p| p.pinned().map(|keep| (p, keep)))
+                .for_each(|(p, keep)| println!("    {} ({keep})", style(p.path().display()).dim()));
+        }
+        let n_partially_pinned_projects =
+            projects.values().filter(|p| p.pinned().is_some()).count();
+        if n_partially_pinned_projects > 0 {
+            println!(
+                "  {}",
+                style(format!(
+                    "{} pinned projects only partially cleaned:",
+                    n_partially_pinned_projects
+                ))
+                .yellow()
+            );
+            projects
+                .values()
+                .filter_map(|p| p.pinned().map(|keep| (p, keep)))
+                .for_each(|(p, keep)| println!("    {} ({keep})", style(p.path().display()).dim()));
+        }
+        if !kept.is_empty() {
+            println!(
+                "  {}",
+                style(format!(
+                    "{} directories not removed, as they contain files that are not ignored by version control or mount points:",
+                    kept.len()
+                ))
+                .yellow()
+            );
+            kept.iter().for_each(|kept| {
+                println!("    {} ({})", style(kept.path.display()).dim(), kept.reason)
+            });
+        }
+        if !unreadable_projects.is_empty() {
+            println!(
+                "  {}",
+                style(format!(
+                    "{} projects not cleaned, as some of their files could not be read (use --force to clean them anyway):",
+                    unreadable_projects.len()
+                ))
+                .yellow()
+            );
+            unreadable_projects
+                .iter()
+                .for_each(|p| println!("    {}", style(p.path().display()).dim()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes the projects whose dependencies might not be restored exactly
+/// from `projects`, unless `confirm` allows cleaning them.
+fn take_unreproducible_projects(
+    projects: &mut HashMap<PathBuf, Project>,
+    mut confirm: impl FnMut(&Project, &[String]) -> anyhow::Result<bool>,
+) -> anyhow::Result<Vec<Project>> {
+    let mut candidates: Vec<&Project> = projects.values().collect();
+    candidates.sort_by_key(|project| project.path());
+    let mut paths = Vec::new();
+    for project in candidates {
+        let risks = project.reproducibility_risks();
+        if !risks.is_empty() && !confirm(project, &risks)? {
+            paths.push(project.path().to_owned());
+        }
     }
+    Ok(paths
+        .into_iter()
+        .map(|path| projects.remove(&path).expect("must be there"))
+        .collect())
+}
+
+/// Restores what a previous run removed or archived, as far as possible.
+pub fn undo(args: &UndoArgs) -> anyhow::Result<()> {
+    let entries = journal::read_entries()?;
+    let run_id = match &args.run_id {
+        Some(run_id) => run_id.as_str(),
+        None => match journal::last_recoverable_run(&entries) {
+            Some(run_id) => run_id,
+            None => {
+                println!("Nothing to undo.");
+                return Ok(());
+            }
+        },
+    };
+    let run_entries: Vec<&JournalEntry> = entries
+        .iter()
+        .filter(|entry| entry.run_id == run_id)
+        .collect();
+    if run_entries.is_empty() {
+        anyhow::bail!("There is no run {run_id:?} in the journal");
+    }
+
+    // In reverse order, so archived projects are extracted before the
+    // directories removed from them are restored
+    let mut n_failed = 0;
+    for entry in run_entries.into_iter().rev() {
+        match entry.restore() {
+            Ok(()) => println!("Restored {}", entry.path.display()),
+            Err(e) => {
+                n_failed += 1;
+                let message = format!("Cannot restore {}: {e:#}", entry.path.display());
+                if colors_enabled() {
+                    println!("{}", style(message).yellow());
+                } else {
+                    println!("{message}");
+                }
+            }
+        }
+    }
+    if n_failed > 0 {
+        println!();
+        println!("{n_failed} of the removed files and directories could not be restored.");
+    }
+    Ok(())
+}
 
+/// Restores the staged directories of the projects in a directory.
+pub fn restore(args: &RestoreArgs) -> anyhow::Result<()> {
+    let directory = args
+        .directory
+        .canonicalize()
+        .with_context(|| format!("Failed to restore {}", args.directory.display()))?;
+    let entries = journal::read_entries()?;
+
+    let mut n_restored = 0;
+    for entry in entries.iter().rev().filter(|entry| {
+        entry.action == Action::Stage
+            && entry.project.starts_with(&directory)
+            && entry.is_recoverable()
+    }) {
+        entry
+            .restore()
+            .with_context(|| format!("Cannot restore {}", entry.path.display()))?;
+        println!("Restored {}", entry.path.display());
+        n_restored += 1;
+    }
+    if n_restored == 0 {
+        println!("Nothing staged for deletion in {}.", directory.display());
+    }
     Ok(())
 }
 
+fn open_index(cli: &Cli) -> ScanIndex {
+    if cli.no_cache {
+        ScanIndex::disabled()
+    } else {
+        ScanIndex::open(true)
+    }
+}
+
+fn save_index(index: &ScanIndex) {
+    if let Err(e) = index.save() {
+        warn!("Failed to save the scan index: {e:#}");
+    }
+}
+
+/// How the files are removed, according to `--trash` and `--stage`.
+fn removal_for(cli: &Cli) -> Removal {
+    if cli.trash {
+        Removal::Trash
+    } else if cli.stage {
+        Removal::Stage
+    } else {
+        Removal::Delete
+    }
+}
+
 fn theme() -> Box<dyn Theme> {
     if colors_enabled() {
         Box::<ColorfulTheme>::default()
@@ -272,19 +773,69 @@ fn pretty_print_project(project: &Project) -> anyhow::Result<()> {
         0 => String::new(),
         bytes => format!("; {}", format_size(bytes)),
     };
+    // Broken down by category if there's more than one
+    let category_usage: Vec<String> = project
+        .category_disk_usage()
+        .into_iter()
+        .filter(|(category, usage)| {
+            project.categories().selects(Some(*category)) && usage.freeable_bytes > 0
+        })
+        .map(|(category, usage)| format!("{category} {}", format_size(usage.freeable_bytes)))
+        .collect();
+    let freeable = if category_usage.len() > 1 {
+        format!("{freeable}: {}", category_usage.join(", "))
+    } else {
+        freeable
+    };
     let mtime = project.mtime().human_readable_elapsed();
+    let pinned = match project.pinned() {
+        Some(keep) => format!("; {keep}"),
+        None => String::new(),
+    };
 
     let path = ProjectPath::from(project).render(use_color);
 
     let line = if use_color {
-        let info = style(format!("({tools}; {vcs}; {mtime}{freeable})")).dim();
+        let info = style(format!("({tools}; {vcs}; {mtime}{freeable}{pinned})")).dim();
         format!("{} {}", path, info)
     } else {
-        format!("{path} ({tools}; {vcs}; {mtime}{freeable})")
+        format!("{path} ({tools}; {vcs}; {mtime}{freeable}{pinned})")
     };
 
     println!("{line}");
 
+    let symlinks = project.symlinked_ephemeral_dirs().iter().map(|dir| {
+        format!(
+            "{} is a symbolic link; only the link is removed",
+            dir.display()
+        )
+    });
+    let mount_points = project.ephemeral_mount_points().iter().map(|mount_point| {
+        format!(
+            "{} is a mount point; neither it nor the directory containing it is removed",
+            mount_point.display()
+        )
+    });
+    let risks = project
+        .reproducibility_risks()
+        .into_iter()
+        .map(|risk| format!("dependencies might not be restored exactly: {risk}"));
+    for error in project
+        .errors()
+        .iter()
+        .cloned()
+        .chain(symlinks)
+        .chain(mount_points)
+        .chain(risks)
+    {
+        let warning = format!("  warning: {error}");
+        if use_color {
+            println!("{}", style(warning).yellow());
+        } else {
+            println!("{warning}");
+        }
+    }
+
     Ok(())
 }
 
@@ -1,21 +1,57 @@
-use clap::StructOpt;
-use std::{env, panic};
+use std::{env, panic, path::PathBuf};
 
-use makeclean::{build_tool_manager::BuildToolManager, Cli};
+use makeclean::{
+    build_tool_manager::BuildToolManager,
+    build_tools::{custom, external},
+    config::Config,
+    Cli, Command,
+};
 
 fn main() -> anyhow::Result<()> {
     setup_panic_hooks();
     setup_logging();
 
-    let cli = Cli::parse();
-
+    // The build tools declared in the configuration are listed in the help
+    // text, so they're registered before the arguments are parsed for real
     let mut build_tool_manager = BuildToolManager::default();
+    let (config_path, search_root) = match Cli::parse_leniently() {
+        Some(cli) => (cli.config, cli.directories.into_iter().next()),
+        None => (None, None),
+    };
+    let search_root = search_root.unwrap_or_else(|| PathBuf::from("."));
+    let config = Config::load(config_path.as_deref(), &search_root).and_then(|config| {
+        custom::register(&mut build_tool_manager, &config.custom_tools)?;
+        external::register(&mut build_tool_manager, &config.external_probes);
+        Ok(config)
+    });
+
+    let mut cli = Cli::parse_with(&build_tool_manager);
+    match &cli.command {
+        Some(Command::Undo(args)) => return makeclean::undo(args),
+        Some(Command::Restore(args)) => return makeclean::restore(args),
+        None => {}
+    }
+
+    // Command-line arguments take precedence over the configuration files
+    let config = config?.merge_cli(&cli);
+    if cli.print_config {
+        print!("{}", config.to_toml()?);
+        return Ok(());
+    }
+    cli.apply_config(&config)?;
+
+    let disabled_types = config.disabled_types();
+    if !disabled_types.is_empty() {
+        build_tool_manager.discard(&disabled_types);
+    }
     let project_types = &cli.types;
     if !project_types.is_empty() {
-        build_tool_manager.filter(project_types);
+        build_tool_manager.filter(project_types)?;
     }
 
-    if cli.list {
+    if cli.list_types {
+        makeclean::list_types(cli, build_tool_manager)
+    } else if cli.list {
         makeclean::list(cli, build_tool_manager)
     } else {
         makeclean::clean(cli, build_tool_manager)
@@ -32,7 +68,7 @@ fn setup_panic_hooks() {
 
     let _default_hook = panic::take_hook();
 
-    panic::set_hook(Box::new(move |info: &panic::PanicInfo| {
+    panic::set_hook(Box::new(move |info| {
         // // First call the default hook that prints to standard error.
         // default_hook(info);
 
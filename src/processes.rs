@@ -0,0 +1,168 @@
+//! Finds files and directories that are in use by running processes.
+//!
+//! Cleaning a project while it's in use breaks things, e.g., removing
+//! `node_modules` while a development server is running, or `target` while
+//! rust-analyzer is building (and holding Cargo's `.cargo-lock`). On Linux,
+//! the working directories, open files and memory-mapped files of all
+//! processes are looked up in `/proc`. Processes of other users are only
+//! visible to privileged users. On other platforms, nothing is found.
+
+use std::path::{Path, PathBuf};
+
+/// A running process that uses a file or directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessUse {
+    pub pid: u32,
+    /// The name of the process' executable, as shown by `ps`.
+    pub name: String,
+    /// The working directory, an open file, or a memory-mapped file.
+    pub path: PathBuf,
+}
+
+/// What all running processes use, looked up once.
+#[derive(Debug, Default)]
+pub struct ProcessUses {
+    uses: Vec<ProcessUse>,
+}
+
+impl ProcessUses {
+    /// Looks up what all running processes use, except for this process and
+    /// its parents (e.g., the shell it's run from).
+    #[cfg(target_os = "linux")]
+    pub fn scan() -> Self {
+        Self {
+            uses: linux::scan(Path::new("/proc")),
+        }
+    }
+
+    /// Looks up what all running processes use; not supported on this
+    /// platform.
+    #[cfg(not(target_os = "linux"))]
+    pub fn scan() -> Self {
+        Self::default()
+    }
+
+    /// The first process found that uses a file or directory at or below
+    /// `dir`.
+    pub fn user_of(&self, dir: &Path) -> Option<&ProcessUse> {
+        self.uses
+            .iter()
+            .find(|process| process.path.starts_with(dir))
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{
+        collections::HashSet,
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    use super::ProcessUse;
+
+    pub(super) fn scan(proc_dir: &Path) -> Vec<ProcessUse> {
+        let own_processes = ancestors(proc_dir, std::process::id());
+        let pids = match fs::read_dir(proc_dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok())
+                .filter(|pid| !own_processes.contains(pid)),
+            Err(_) => return Vec::new(),
+        };
+
+        let mut uses = Vec::new();
+        for pid in pids {
+            // Processes may exit at any time, and those of other users are
+            // usually not accessible, so errors are simply skipped
+            let dir = proc_dir.join(pid.to_string());
+            let name = fs::read_to_string(dir.join("comm"))
+                .map(|comm| comm.trim_end().to_owned())
+                .unwrap_or_default();
+            let mut paths: HashSet<PathBuf> = HashSet::new();
+            if let Ok(cwd) = fs::read_link(dir.join("cwd")) {
+                paths.insert(cwd);
+            }
+            if let Ok(fds) = fs::read_dir(dir.join("fd")) {
+                paths.extend(
+                    fds.filter_map(|fd| fd.ok())
+                        .filter_map(|fd| fs::read_link(fd.path()).ok())
+                        // Sockets, pipes and the like aren't files
+                        .filter(|path| path.is_absolute()),
+                );
+            }
+            if let Ok(maps) = fs::read_to_string(dir.join("maps")) {
+                paths.extend(mapped_files(&maps));
+            }
+            uses.extend(paths.into_iter().map(|path| ProcessUse {
+                pid,
+                name: name.clone(),
+                path,
+            }));
+        }
+        uses
+    }
+
+    /// The files listed in the contents of `/proc/$pid/maps`.
+    pub(super) fn mapped_files(maps: &str) -> impl Iterator<Item = PathBuf> + '_ {
+        maps.lines().filter_map(|line| {
+            // address perms offset dev inode pathname; the pathname may
+            // contain spaces
+            let path = line.splitn(6, char::is_whitespace).nth(5)?.trim_start();
+            let path = path.strip_suffix(" (deleted)").unwrap_or(path);
+            path.starts_with('/').then(|| PathBuf::from(path))
+        })
+    }
+
+    /// The process with the given ID and all of its parents.
+    fn ancestors(proc_dir: &Path, pid: u32) -> HashSet<u32> {
+        let mut ancestors = HashSet::new();
+        let mut pid = pid;
+        while pid > 0 && ancestors.insert(pid) {
+            pid = match fs::read_to_string(proc_dir.join(pid.to_string()).join("stat")) {
+                Ok(stat) => parent_pid(&stat).unwrap_or(0),
+                Err(_) => 0,
+            };
+        }
+        ancestors
+    }
+
+    /// The parent process ID, as found in the contents of `/proc/$pid/stat`.
+    pub(super) fn parent_pid(stat: &str) -> Option<u32> {
+        // The executable name is in parentheses and may contain anything,
+        // so the fields are counted from the last closing parenthesis
+        let (_, fields) = stat.rsplit_once(')')?;
+        fields.split_whitespace().nth(1)?.parse().ok()
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use std::path::PathBuf;
+
+    use super::linux::{mapped_files, parent_pid};
+
+    #[test]
+    fn mapped_files_are_parsed() {
+        let maps = "\
+55d0c1a3c000-55d0c1a3e000 r--p 00000000 fd:01 1234    /usr/bin/cat
+7f2d1c000000-7f2d1c021000 rw-p 00000000 00:00 0 
+7f2d1c400000-7f2d1c600000 r--p 00000000 fd:01 5678    /home/user/my project/target/debug/libfoo.so (deleted)
+7ffd3b5e8000-7ffd3b609000 rw-p 00000000 00:00 0       [stack]
+";
+        assert_eq!(
+            mapped_files(maps).collect::<Vec<_>>(),
+            vec![
+                PathBuf::from("/usr/bin/cat"),
+                PathBuf::from("/home/user/my project/target/debug/libfoo.so")
+            ]
+        );
+    }
+
+    #[test]
+    fn the_parent_pid_is_found_despite_odd_executable_names() {
+        assert_eq!(parent_pid("42 (cargo) S 7 42 42 0 -1"), Some(7));
+        assert_eq!(parent_pid("42 (odd) name) R 1 42 42 0 -1"), Some(1));
+        assert_eq!(parent_pid("garbage"), None);
+    }
+}
@@ -5,20 +5,32 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use xz::write::XzEncoder;
+use xz::{read::XzDecoder, write::XzEncoder};
 
-use crate::Project;
+use crate::{fs::rename_to_available, Project};
 
 impl Project {
+    /// Where [`Project::archive`] puts the archive.
+    pub fn archive_path(&self) -> PathBuf {
+        self.path.join(self.archive_file_name())
+    }
+
+    fn archive_file_name(&self) -> String {
+        format!("{}.tar.xz", self.name)
+    }
+
     /// Move the project's files into an archive.
-    pub fn archive(&mut self, dry_run: bool) -> Result<PathBuf> {
+    ///
+    /// `compression_level` is the xz preset to use, from 0 (fastest) to 9
+    /// (smallest).
+    pub fn archive(&mut self, compression_level: u32) -> Result<PathBuf> {
         // The archive is created in a temporary directory. On success, the
         // project directory is renamed, then the archive is moved to the
         // project's original location, then the renamed project directory is
         // removed.
 
-        let tar_xz_fname = format!("{}.tar.xz", self.name);
-        let final_tar_xz_path = self.path.join(&tar_xz_fname);
+        let tar_xz_fname = self.archive_file_name();
+        let final_tar_xz_path = self.archive_path();
 
         if final_tar_xz_path.exists() {
             bail!(
@@ -27,19 +39,10 @@ impl Project {
             );
         }
 
-        if dry_run {
-            println!(
-                "Would replace '{}/*' with {:?}",
-                self.path.display(),
-                final_tar_xz_path
-            );
-            return Ok(final_tar_xz_path);
-        }
-
         // Create the archive in a temporary directory
         let tempdir = TempDir::new()?;
         let temp_tar_xz = tempdir.child(&tar_xz_fname);
-        create_tar_xz(&self.path, temp_tar_xz.path())?;
+        create_tar_xz(&self.path, temp_tar_xz.path(), compression_level)?;
 
         // Rename the project directory
         let renamed_project_path = rename_project_dir(&self.path)?;
@@ -63,9 +66,9 @@ impl Project {
     }
 }
 
-fn create_tar_xz(src_dir: &Path, dst_path: &Path) -> anyhow::Result<()> {
+fn create_tar_xz(src_dir: &Path, dst_path: &Path, compression_level: u32) -> anyhow::Result<()> {
     let tar_xz = File::create(dst_path)?;
-    let xz = XzEncoder::new(tar_xz, 6);
+    let xz = XzEncoder::new(tar_xz, compression_level);
     let mut tar = tar::Builder::new(xz);
     tar.append_dir_all(".", src_dir)?;
     let xz = tar.into_inner()?;
@@ -73,6 +76,16 @@ fn create_tar_xz(src_dir: &Path, dst_path: &Path) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Unpacks an archive created by [`Project::archive`] into `dst_dir`.
+pub(crate) fn extract_tar_xz(src_path: &Path, dst_dir: &Path) -> anyhow::Result<()> {
+    let tar_xz = File::open(src_path)?;
+    let mut tar = tar::Archive::new(XzDecoder::new(tar_xz));
+    tar.set_preserve_mtime(true);
+    tar.set_preserve_permissions(true);
+    tar.unpack(dst_dir)?;
+    Ok(())
+}
+
 fn rename_project_dir(project_path: &Path) -> anyhow::Result<PathBuf> {
     let project_path = project_path.canonicalize()?;
 
@@ -86,20 +99,10 @@ fn rename_project_dir(project_path: &Path) -> anyhow::Result<PathBuf> {
         .expect("canonicalized never ends with '.' or '..'");
 
     // Find an available name
-    let mut new_path = None;
-    for i in 1..10 {
-        let candidate = parent.join(format!(".{}~{i}", project_dir_name.to_string_lossy()));
-        if !candidate.exists() {
-            new_path = Some(candidate);
-            break;
-        }
-    }
-
-    if let Some(new_path) = new_path {
-        fs::rename(&project_path, &new_path)
-            .with_context(|| format!("Failed to rename {:?} to {:?}", project_path, new_path))?;
-        Ok(new_path)
-    } else {
-        bail!("Could not move the project directory after archiving it. Please make sure there are no '.{}~*' directories at {}", project_dir_name.to_string_lossy(), parent.display())
+    let candidates =
+        (1..10).map(|i| parent.join(format!(".{}~{i}", project_dir_name.to_string_lossy())));
+    match rename_to_available(&project_path, candidates)? {
+        Some(new_path) => Ok(new_path),
+        None => bail!("Could not move the project directory after archiving it. Please make sure there are no '.{}~*' directories at {}", project_dir_name.to_string_lossy(), parent.display()),
     }
 }
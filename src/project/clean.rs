@@ -1,15 +1,104 @@
+use std::path::PathBuf;
+
 use tracing::trace;
 
 use super::Project;
+use crate::build_tools::{reason_to_keep, CleanStep, Removal, Remover};
 
 impl Project {
-    /// Invokes `clean` on each of the build tools used in this project.
-    pub fn clean(&mut self, dry_run: bool) -> anyhow::Result<()> {
-        trace!(?self.path, "cleaning project");
-        assert!(!self.build_tools.is_empty());
-        for build_tool in self.build_tools.iter_mut() {
-            build_tool.clean_project(dry_run)?;
+    /// Cleans the project by executing its [clean plan](Project::clean_plan).
+    ///
+    /// What is removed is remembered by `remover`. The project's status
+    /// isn't updated; see [`Project::refresh_status`].
+    pub fn clean(&mut self, remover: &Remover) -> anyhow::Result<()> {
+        for step in self.clean_plan(remover.removal())? {
+            remover.execute(&step)?;
         }
         Ok(())
     }
+
+    /// How to clean the project, according to its build tools; see
+    /// [`BuildTool::clean_plan`](crate::build_tools::BuildTool::clean_plan).
+    ///
+    /// Pinned projects are left alone, unless they're pinned with the
+    /// "dependencies" scope, which is part of the [categories
+    /// cleaned](Project::categories). If only some categories are cleaned,
+    /// the build tools aren't invoked; instead, the directories of those
+    /// categories are removed directly.
+    ///
+    /// Paths that must not be removed are kept (see [`reason_to_keep`]), and
+    /// the steps are annotated with the expected sizes. As a command can't be
+    /// told to leave a path alone, a command that would remove such a path is
+    /// replaced by removing the other paths directly.
+    pub fn clean_plan(&self, removal: Removal) -> anyhow::Result<Vec<CleanStep>> {
+        assert!(!self.build_tools.is_empty());
+        let mut steps = Vec::new();
+        if self.is_pinned_entirely() {
+            trace!(?self.path, "not cleaning pinned project");
+        } else if self.categories.is_active() {
+            trace!(?self.path, categories = ?self.categories, "cleaning selected categories");
+            for path in self.selected_ephemeral_paths() {
+                steps.push(CleanStep::remove(path.path.clone()));
+            }
+        } else {
+            trace!(?self.path, "cleaning project");
+            for build_tool in &self.build_tools {
+                steps.extend(build_tool.clean_plan(removal)?);
+            }
+        }
+
+        let usage_of = |path: &PathBuf| {
+            self.ephemeral_paths
+                .iter()
+                .find(|ephemeral_path| ephemeral_path.path == *path)
+                .map(|ephemeral_path| ephemeral_path.usage)
+                .unwrap_or_default()
+        };
+        let remove_or_keep = |path: PathBuf, reason: Option<String>| match reason {
+            Some(reason) => CleanStep::Keep { path, reason },
+            None => CleanStep::Remove {
+                usage: usage_of(&path),
+                path,
+            },
+        };
+        let mut plan = Vec::new();
+        for step in steps {
+            match step {
+                CleanStep::Remove { path, .. } => {
+                    let reason = reason_to_keep(&path);
+                    plan.push(remove_or_keep(path, reason));
+                }
+                CleanStep::Run {
+                    program,
+                    args,
+                    dir,
+                    removes,
+                    ..
+                } => {
+                    let reasons: Vec<Option<String>> =
+                        removes.iter().map(|path| reason_to_keep(path)).collect();
+                    if reasons.iter().any(|reason| reason.is_some()) {
+                        trace!(?program, ?args, "not running, as some paths must be kept");
+                        for (path, reason) in removes.into_iter().zip(reasons) {
+                            plan.push(remove_or_keep(path, reason));
+                        }
+                    } else {
+                        let mut usage = Default::default();
+                        for path in &removes {
+                            usage += usage_of(path);
+                        }
+                        plan.push(CleanStep::Run {
+                            program,
+                            args,
+                            dir,
+                            removes,
+                            usage,
+                        });
+                    }
+                }
+                step @ CleanStep::Keep { .. } => plan.push(step),
+            }
+        }
+        Ok(plan)
+    }
 }
@@ -1,11 +1,19 @@
 //! Representation of a [`Project`] used for JSON output.
 
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 use time::format_description::well_known::Rfc3339;
 
-use crate::Project;
+use crate::{
+    build_tools::{BuildToolProbe, Category, CleanStep, EphemeralPath},
+    Project,
+};
 
-use super::vcs::VersionControlSystem;
+use super::{
+    keep::{Keep, KeepScope},
+    vcs::VersionControlSystem,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ProjectDto {
@@ -22,6 +30,42 @@ pub struct ProjectDto {
     pub vcs: Option<VcsDto>,
     /// When this project was last modified (most recent commit timestamp), in RFC3339.
     pub mtime: String,
+    /// Set if the project is pinned, i.e., protected from cleaning.
+    #[serde(default)]
+    pub pinned: Option<PinnedDto>,
+    /// How much disk space cleaning the project would free up.
+    #[serde(default)]
+    pub freeable_bytes: u64,
+    /// The total size of the files cleaning the project would remove.
+    #[serde(default)]
+    pub apparent_bytes: u64,
+    /// Files and directories within the project that could not be read.
+    #[serde(default)]
+    pub errors: Vec<String>,
+    /// Directories that would be removed, but are symbolic links; only the
+    /// links are removed.
+    #[serde(default)]
+    pub symlinked_ephemeral_dirs: Vec<String>,
+    /// Mount points at or within directories that would be removed; such
+    /// directories are not removed.
+    #[serde(default)]
+    pub ephemeral_mount_points: Vec<String>,
+    /// The existing files and directories that cleaning the project would
+    /// remove, with their category and size.
+    #[serde(default)]
+    pub ephemeral_paths: Vec<EphemeralPath>,
+    /// How much disk space cleaning each category of ephemeral directories
+    /// would free up, e.g., `"dependencies": 1024`.
+    #[serde(default)]
+    pub category_bytes: BTreeMap<Category, u64>,
+    /// Why the dependencies removed by cleaning the project might not be
+    /// restored exactly as they were, e.g., because there is no lockfile.
+    #[serde(default)]
+    pub reproducibility_risks: Vec<String>,
+    /// What cleaning the project would do, in order; only set by
+    /// `makeclean --dry-run --json`, for the projects that would be cleaned.
+    #[serde(default)]
+    pub clean_plan: Option<Vec<CleanStep>>,
 }
 
 impl From<&Project> for ProjectDto {
@@ -39,6 +83,48 @@ impl From<&Project> for ProjectDto {
                 .mtime
                 .format(&Rfc3339)
                 .expect("can format as RFC3339"),
+            pinned: project.pinned.as_ref().map(PinnedDto::from),
+            freeable_bytes: project.freeable_bytes,
+            apparent_bytes: project.apparent_bytes,
+            errors: project.errors.clone(),
+            symlinked_ephemeral_dirs: project
+                .symlinked_ephemeral_dirs
+                .iter()
+                .map(|dir| dir.display().to_string())
+                .collect(),
+            ephemeral_mount_points: project
+                .ephemeral_mount_points
+                .iter()
+                .map(|dir| dir.display().to_string())
+                .collect(),
+            ephemeral_paths: project.ephemeral_paths().to_vec(),
+            category_bytes: project
+                .category_disk_usage()
+                .into_iter()
+                .map(|(category, usage)| (category, usage.freeable_bytes))
+                .collect(),
+            reproducibility_risks: project.reproducibility_risks(),
+            clean_plan: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PinnedDto {
+    /// The last day the project is pinned, if the pin expires.
+    pub until: Option<String>,
+    /// Either "all" or "dependencies".
+    pub scope: KeepScope,
+    /// Why the project is pinned.
+    pub reason: Option<String>,
+}
+
+impl From<&Keep> for PinnedDto {
+    fn from(keep: &Keep) -> Self {
+        Self {
+            until: keep.until.clone(),
+            scope: keep.scope,
+            reason: keep.reason.clone(),
         }
     }
 }
@@ -56,3 +142,28 @@ impl From<&VersionControlSystem> for VcsDto {
         Self { name, root }
     }
 }
+
+/// Describes a project type, as printed by `--list-types`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BuildToolProbeDto {
+    /// The canonical name of the project type.
+    pub id: String,
+    /// Alternative names of the project type.
+    pub aliases: Vec<String>,
+    /// The directories that are typically removed when cleaning.
+    pub ephemeral_dirs: Vec<String>,
+}
+
+impl From<&dyn BuildToolProbe> for BuildToolProbeDto {
+    fn from(probe: &dyn BuildToolProbe) -> Self {
+        Self {
+            id: probe.id().to_owned(),
+            aliases: probe.aliases().into_iter().map(str::to_owned).collect(),
+            ephemeral_dirs: probe
+                .ephemeral_dirs()
+                .into_iter()
+                .map(str::to_owned)
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,220 @@
+//! Pinned projects, which are never (fully) cleaned.
+//!
+//! A project is pinned if its directory contains a `.makeclean-keep` file, or
+//! if it's listed in a `[[keep]]` section of the configuration file. The file
+//! may be empty, which pins the project indefinitely, or contain settings in
+//! TOML format:
+//!
+//! ```toml
+//! # The pin expires at the end of that day:
+//! until = 2023-12-31
+//! # "all" (the default) keeps the project as it is; "dependencies" still
+//! # allows cleaning build outputs:
+//! scope = "dependencies"
+//! reason = "Demo at the conference"
+//! ```
+
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Deserializer, Serialize};
+use time::{macros::format_description, Date, OffsetDateTime};
+use tracing::trace;
+
+/// The name of the file that pins the project in its directory.
+pub const KEEP_FILE_NAME: &str = ".makeclean-keep";
+
+/// Pins a project.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Keep {
+    /// The project directory; only used in the configuration file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    /// The last day (in UTC) the project is pinned, formatted as `YYYY-MM-DD`.
+    #[serde(
+        deserialize_with = "deserialize_date",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub until: Option<String>,
+    /// What is kept.
+    pub scope: KeepScope,
+    /// Why the project is pinned.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// What is kept in a pinned project.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeepScope {
+    /// The project is not touched at all.
+    All,
+    /// Downloaded dependencies are kept, but build outputs are cleaned.
+    Dependencies,
+}
+
+impl Default for KeepScope {
+    fn default() -> Self {
+        Self::All
+    }
+}
+
+impl Keep {
+    /// Reads the keep file in the given project directory, if any.
+    pub fn from_dir(path: &Path) -> anyhow::Result<Option<Self>> {
+        let keep_file = path.join(KEEP_FILE_NAME);
+        if !keep_file.is_file() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&keep_file)?;
+        let keep: Keep = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", keep_file.display()))?;
+        keep.validate()
+            .with_context(|| format!("Invalid {}", keep_file.display()))?;
+        Ok(Some(keep))
+    }
+
+    /// The last day the project is pinned, if the pin expires.
+    pub fn until(&self) -> anyhow::Result<Option<Date>> {
+        self.until
+            .as_deref()
+            .map(|until| {
+                Date::parse(until, format_description!("[year]-[month]-[day]"))
+                    .with_context(|| format!("Invalid date {until:?}, expected YYYY-MM-DD"))
+            })
+            .transpose()
+    }
+
+    /// Whether the pin is still in effect.
+    pub fn is_active(&self, now: OffsetDateTime) -> bool {
+        match self.until() {
+            Ok(Some(until)) => now.date() <= until,
+            Ok(None) => true,
+            // Better safe than sorry
+            Err(_) => true,
+        }
+    }
+
+    pub(crate) fn validate(&self) -> anyhow::Result<()> {
+        self.until()?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Keep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "pinned")?;
+        if let Some(until) = &self.until {
+            write!(f, " until {until}")?;
+        }
+        if self.scope == KeepScope::Dependencies {
+            write!(f, ", keeping dependencies")?;
+        }
+        Ok(())
+    }
+}
+
+/// Finds the pin that applies to the project at `path`, if any.
+///
+/// The project's keep file takes precedence over `configured` pins. Expired
+/// pins are ignored.
+pub(crate) fn pin_for(
+    path: &Path,
+    configured: &[Keep],
+    now: OffsetDateTime,
+) -> anyhow::Result<Option<Keep>> {
+    let keep = match Keep::from_dir(path)? {
+        Some(keep) => Some(keep),
+        None => configured
+            .iter()
+            .find(|keep| keep.path.as_deref() == Some(path))
+            .cloned(),
+    };
+
+    match keep {
+        Some(keep) if !keep.is_active(now) => {
+            trace!(?path, until = ?keep.until, "Ignoring expired pin");
+            Ok(None)
+        }
+        keep => Ok(keep),
+    }
+}
+
+/// Accepts both TOML dates and strings.
+fn deserialize_date<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum DateValue {
+        Toml(toml::value::Datetime),
+        Text(String),
+    }
+
+    Ok(
+        Option::<DateValue>::deserialize(deserializer)?.map(|value| match value {
+            DateValue::Toml(datetime) => datetime.to_string(),
+            DateValue::Text(text) => text,
+        }),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use assert_fs::{
+        fixture::{FileTouch, FileWriteStr, PathChild},
+        TempDir,
+    };
+    use time::macros::datetime;
+
+    use super::*;
+
+    #[test]
+    fn an_empty_keep_file_pins_the_project_forever() {
+        let root = TempDir::new().unwrap();
+        root.child(KEEP_FILE_NAME).touch().unwrap();
+
+        let keep = pin_for(root.path(), &[], datetime!(2100-01-01 0:00 UTC))
+            .unwrap()
+            .unwrap();
+        assert_eq!(keep.scope, KeepScope::All);
+    }
+
+    #[test]
+    fn pins_expire_after_the_given_day() {
+        let root = TempDir::new().unwrap();
+        root.child(KEEP_FILE_NAME)
+            .write_str("until = 2022-03-31\nscope = \"dependencies\"")
+            .unwrap();
+
+        let keep = pin_for(root.path(), &[], datetime!(2022-03-31 23:59 UTC))
+            .unwrap()
+            .unwrap();
+        assert_eq!(keep.scope, KeepScope::Dependencies);
+
+        let keep = pin_for(root.path(), &[], datetime!(2022-04-01 0:00 UTC)).unwrap();
+        assert!(keep.is_none());
+    }
+
+    #[test]
+    fn configured_pins_match_by_path() {
+        let root = TempDir::new().unwrap();
+        let configured = vec![Keep {
+            path: Some(root.path().to_owned()),
+            until: Some("2022-03-31".to_owned()),
+            ..Keep::default()
+        }];
+
+        let keep = pin_for(root.path(), &configured, datetime!(2022-01-01 0:00 UTC)).unwrap();
+        assert!(keep.is_some());
+
+        let other = root.child("other");
+        let keep = pin_for(other.path(), &configured, datetime!(2022-01-01 0:00 UTC)).unwrap();
+        assert!(keep.is_none());
+    }
+}
@@ -1,24 +1,34 @@
 //! Represents a software project.
 
-mod archive;
+pub(crate) mod archive;
 mod clean;
 pub mod dto;
+pub mod keep;
 pub mod mtime;
-mod vcs;
+pub mod scan;
+pub(crate) mod vcs;
 
 use crate::{
     build_tool_manager::BuildToolManager,
-    build_tools::{BuildStatus, BuildTool},
+    build_tools::{BuildStatus, BuildTool, Category, EphemeralPath},
+    fs::{DiskUsage, Hardlinks},
+    index::{fingerprint, IndexEntry, ScanIndex},
 };
 use anyhow::format_err;
+use globset::GlobSet;
 use std::{
+    collections::BTreeMap,
     fmt,
     path::{Path, PathBuf},
 };
-use time::{Duration, OffsetDateTime};
+use time::{format_description::well_known::Rfc3339, Duration, OffsetDateTime};
 use tracing::{trace, warn};
 
-use self::{mtime::dir_mtime, vcs::VersionControlSystem};
+use self::{
+    keep::{pin_for, Keep, KeepScope},
+    scan::ProjectScan,
+    vcs::VersionControlSystem,
+};
 
 /// Main entity.
 #[derive(Debug)]
@@ -28,6 +38,27 @@ pub struct Project {
     build_tools: Vec<Box<dyn BuildTool>>,
     vcs: Option<VersionControlSystem>,
     mtime: OffsetDateTime,
+    pinned: Option<Keep>,
+    /// The status reported by each build tool, in the same order.
+    status: Vec<BuildStatus>,
+    /// The disk usage of each existing ephemeral directory.
+    ephemeral_disk_usage: BTreeMap<PathBuf, DiskUsage>,
+    /// Git repositories within the project directory.
+    nested_repositories: Vec<PathBuf>,
+    /// Files and directories within the project that could not be read.
+    errors: Vec<String>,
+    /// Ephemeral directories that are symbolic links.
+    symlinked_ephemeral_dirs: Vec<PathBuf>,
+    /// Mount points at or within ephemeral directories.
+    ephemeral_mount_points: Vec<PathBuf>,
+    /// What cleaning the project would remove, as reported by the build tools.
+    ephemeral_paths: Vec<EphemeralPath>,
+    /// Which categories of ephemeral directories are cleaned.
+    categories: CategoryFilter,
+    /// Derived from `status`, respecting the pin.
+    freeable_bytes: u64,
+    /// Derived from `status`, respecting the pin.
+    apparent_bytes: u64,
 }
 
 impl Project {
@@ -36,53 +67,201 @@ impl Project {
         project_filter: &ProjectFilter,
         build_tool_manager: &BuildToolManager,
     ) -> anyhow::Result<Option<Project>> {
-        // Is this a project? => yes, if at least one build tool is recognized
         let build_tools = build_tool_manager.probe(path);
+        Self::from_build_tools(
+            path,
+            build_tools,
+            project_filter,
+            &ScanIndex::disabled(),
+            &Hardlinks::default(),
+        )
+    }
+
+    /// Like [`Project::from_dir`], but with the build tools already probed.
+    ///
+    /// Status, name and modification time are taken from `index` if it has a
+    /// valid entry for the project, and recorded in it otherwise. Files that
+    /// are hard links to files in `hardlinks` are not considered freeable.
+    pub(crate) fn from_build_tools(
+        path: &Path,
+        build_tools: Vec<Box<dyn BuildTool>>,
+        project_filter: &ProjectFilter,
+        index: &ScanIndex,
+        hardlinks: &Hardlinks,
+    ) -> anyhow::Result<Option<Project>> {
+        // Is this a project? => yes, if at least one build tool is recognized
         if build_tools.is_empty() {
             return Ok(None);
         }
 
-        if let StatusFilter::ExceptClean = project_filter.status {
+        let is_filtered_out = |status: &[BuildStatus]| match project_filter.status {
+            StatusFilter::Any => false,
             // Ignore this project if _all_ build tools report a clean state
-            if build_tools
+            StatusFilter::ExceptClean => status
                 .iter()
-                .all(|tool| matches!(tool.status(), Ok(BuildStatus::Clean)))
-            {
-                return Ok(None);
-            }
-        }
-
-        let project_name = match project_name_from(path, &build_tools) {
-            Ok(name) => name,
-            Err(e) => {
-                warn!("Failed to determine project name for {path:?}: {e}");
-                return Ok(None);
-            }
+                .all(|status| matches!(status, BuildStatus::Clean)),
         };
 
-        let mtime = dir_mtime(path)
-            .ok_or_else(|| format_err!("BUG: build tool recognized but no files?!"))?;
         let now = OffsetDateTime::now_utc();
-
-        if (now - mtime) < project_filter.min_stale {
+        let skip_recent = |mtime: Option<OffsetDateTime>| {
             trace!(
                 ?path,
-                %mtime,
+                ?mtime,
                 min_stale=%project_filter.min_stale,
                 "Project skipped due to recent mtime",
             );
+            Ok(None)
+        };
+
+        let ephemeral_dirs = ephemeral_dirs_of(&build_tools);
+        let tool_names = || -> Vec<String> { build_tools.iter().map(|x| x.to_string()).collect() };
+        let candidate = index
+            .candidate(path)
+            .filter(|entry| entry.build_tools == tool_names());
+        // Changes since the entry was recorded could only have made the
+        // project more recent, so it's skipped without computing the
+        // fingerprint, which means looking at all of its directories
+        if let Some(mtime) = candidate
+            .as_ref()
+            .and_then(|entry| entry.mtime())
+            .filter(|mtime| (now - *mtime) < project_filter.min_stale)
+        {
+            return skip_recent(Some(mtime));
+        }
+        let cached = candidate
+            .and_then(|_| index.get(path, fingerprint(path, &ephemeral_dirs)))
+            .and_then(|entry| {
+                let mtime = entry.mtime()?;
+                let scan = ProjectScan {
+                    mtime: Some(mtime),
+                    ephemeral: entry.ephemeral,
+                    nested_repositories: entry.nested_repositories,
+                    errors: entry.errors,
+                    symlinked_ephemeral_dirs: entry.symlinked_ephemeral_dirs,
+                    ephemeral_mount_points: entry.ephemeral_mount_points,
+                };
+                Some((entry.status, entry.name, scan))
+            });
+
+        let (status, project_name, scan) = match cached {
+            Some(cached) => cached,
+            None => {
+                // Finding a single recent file is enough to skip the project,
+                // so the full traversal only happens for stale projects
+                let scan = match ProjectScan::unless_modified_after(
+                    path,
+                    &ephemeral_dirs,
+                    now - project_filter.min_stale,
+                    hardlinks,
+                ) {
+                    Some(scan) => scan,
+                    None => return skip_recent(None),
+                };
+                let status = status_from_scan(path, &build_tools, &scan);
+                if is_filtered_out(&status) {
+                    return Ok(None);
+                }
+
+                let project_name = match project_name_from(path, &build_tools) {
+                    Ok(name) => name,
+                    Err(e) => {
+                        warn!("Failed to determine project name for {path:?}: {e}");
+                        return Ok(None);
+                    }
+                };
+
+                let mtime = scan
+                    .mtime
+                    .ok_or_else(|| format_err!("BUG: build tool recognized but no files?!"))?;
+
+                // Only projects that aren't filtered out are fingerprinted
+                if index.is_enabled() {
+                    index.insert(
+                        path,
+                        IndexEntry {
+                            fingerprint: fingerprint(path, &ephemeral_dirs),
+                            build_tools: tool_names(),
+                            status: status.clone(),
+                            name: project_name.clone(),
+                            mtime: mtime.format(&Rfc3339)?,
+                            ephemeral: scan.ephemeral.clone(),
+                            nested_repositories: scan.nested_repositories.clone(),
+                            errors: scan.errors.clone(),
+                            symlinked_ephemeral_dirs: scan.symlinked_ephemeral_dirs.clone(),
+                            ephemeral_mount_points: scan.ephemeral_mount_points.clone(),
+                        },
+                    );
+                }
+
+                (status, project_name, scan)
+            }
+        };
+        let mtime = scan
+            .mtime
+            .ok_or_else(|| format_err!("BUG: build tool recognized but no files?!"))?;
+
+        if is_filtered_out(&status) {
             return Ok(None);
         }
 
+        if (now - mtime) < project_filter.min_stale {
+            return skip_recent(Some(mtime));
+        }
+
         let vcs = VersionControlSystem::try_from(path)?;
 
-        Ok(Some(Project {
+        let pinned = pin_for(path, &project_filter.keep, now)?;
+
+        // Keeping the dependencies of a pinned project is the same as not
+        // cleaning that category
+        let mut categories = project_filter.categories.clone();
+        if matches!(
+            pinned.as_ref().map(|keep| keep.scope),
+            Some(KeepScope::Dependencies)
+        ) {
+            categories = categories.except(Category::Dependencies);
+        }
+
+        let ephemeral_paths = ephemeral_paths_from(&build_tools, &scan);
+
+        let mut project = Project {
             name: project_name,
             path: path.to_owned(),
             build_tools,
             vcs,
             mtime,
-        }))
+            pinned,
+            status,
+            ephemeral_disk_usage: scan.ephemeral,
+            nested_repositories: scan.nested_repositories,
+            errors: scan.errors,
+            symlinked_ephemeral_dirs: scan.symlinked_ephemeral_dirs,
+            ephemeral_mount_points: scan.ephemeral_mount_points,
+            ephemeral_paths,
+            categories,
+            freeable_bytes: 0,
+            apparent_bytes: 0,
+        };
+        project.update_sizes();
+
+        // With only some of the categories cleaned, a project that is built
+        // might still have nothing to clean
+        let has_nothing_selected = || {
+            project
+                .category_disk_usage()
+                .iter()
+                .all(|(category, usage)| {
+                    !project.categories.selects(Some(*category)) || usage.apparent_bytes == 0
+                })
+        };
+        if project.categories.is_active()
+            && matches!(project_filter.status, StatusFilter::ExceptClean)
+            && has_nothing_selected()
+        {
+            return Ok(None);
+        }
+
+        Ok(Some(project))
     }
 
     /// The name of project.
@@ -103,6 +282,12 @@ impl Project {
         &self.build_tools
     }
 
+    /// The directories removed when cleaning the project, as reported by its
+    /// build tools.
+    pub fn ephemeral_dirs(&self) -> Vec<PathBuf> {
+        ephemeral_dirs_of(&self.build_tools)
+    }
+
     /// The VCS, if under version control.
     pub fn vcs(&self) -> Option<&VersionControlSystem> {
         self.vcs.as_ref()
@@ -113,16 +298,213 @@ impl Project {
         self.mtime
     }
 
-    /// How much space can potentially be freed up by cleaning this project.
-    pub fn freeable_bytes(&self) -> u64 {
+    /// Whether and how the project is pinned, i.e., protected from cleaning.
+    pub fn pinned(&self) -> Option<&Keep> {
+        self.pinned.as_ref()
+    }
+
+    /// The status of each of the build tools, in the same order as
+    /// [`Project::build_tools`].
+    ///
+    /// Determined when the project is found; see [`Project::refresh_status`].
+    pub fn status(&self) -> &[BuildStatus] {
+        &self.status
+    }
+
+    /// The disk usage of each of the [`Project::ephemeral_dirs`] that exist.
+    pub fn ephemeral_disk_usage(&self) -> &BTreeMap<PathBuf, DiskUsage> {
+        &self.ephemeral_disk_usage
+    }
+
+    /// Directories within the project that are Git repositories of their
+    /// own, e.g., vendored dependencies or separate projects.
+    pub fn nested_repositories(&self) -> &[PathBuf] {
+        &self.nested_repositories
+    }
+
+    /// Files and directories within the project that could not be read, e.g.,
+    /// due to missing permissions.
+    ///
+    /// If there are any, the project's size, status and modification time
+    /// might be wrong; in particular, it might look stale only because its
+    /// recently modified files couldn't be read.
+    pub fn errors(&self) -> &[String] {
+        &self.errors
+    }
+
+    /// Those of the [`Project::ephemeral_dirs`] that are symbolic links to
+    /// directories. Cleaning the project only removes the links, as the
+    /// directories they point to might be shared.
+    pub fn symlinked_ephemeral_dirs(&self) -> &[PathBuf] {
+        &self.symlinked_ephemeral_dirs
+    }
+
+    /// Mount points at or within the [`Project::ephemeral_dirs`]. Cleaning
+    /// the project doesn't remove directories that contain a mount point.
+    pub fn ephemeral_mount_points(&self) -> &[PathBuf] {
+        &self.ephemeral_mount_points
+    }
+
+    /// Why the dependencies removed by cleaning this project might not be
+    /// restored exactly as they were; see [`BuildTool::reproducibility`].
+    ///
+    /// Empty for pinned projects, and if dependencies aren't cleaned, as
+    /// their dependencies are kept.
+    pub fn reproducibility_risks(&self) -> Vec<String> {
+        if self.pinned.is_some() || !self.categories.selects(Some(Category::Dependencies)) {
+            return Vec::new();
+        }
         self.build_tools
             .iter()
-            .map(|x| match x.status() {
-                Ok(BuildStatus::Built { freeable_bytes }) => freeable_bytes,
-                _ => 0,
-            })
-            .sum::<u64>()
+            .flat_map(|tool| tool.reproducibility().risks().to_vec())
+            .collect()
+    }
+
+    /// The existing files and directories that cleaning the project would
+    /// remove, with their sizes; see [`BuildTool::ephemeral_paths`].
+    ///
+    /// Neither the project's pin nor the categories cleaned are taken into
+    /// account.
+    pub fn ephemeral_paths(&self) -> &[EphemeralPath] {
+        &self.ephemeral_paths
+    }
+
+    /// Which categories of ephemeral directories are cleaned.
+    ///
+    /// Dependencies are not cleaned if the project is pinned with the
+    /// "dependencies" scope.
+    pub fn categories(&self) -> &CategoryFilter {
+        &self.categories
+    }
+
+    /// The disk usage of the [`Project::ephemeral_dirs`] by their
+    /// [`Category`]; uncategorized directories are left out.
+    pub fn category_disk_usage(&self) -> BTreeMap<Category, DiskUsage> {
+        let mut usage_by_category: BTreeMap<Category, DiskUsage> = BTreeMap::new();
+        for path in &self.ephemeral_paths {
+            if let Some(category) = path.category {
+                *usage_by_category.entry(category).or_default() += path.usage;
+            }
+        }
+        usage_by_category
+    }
+
+    /// The ephemeral directories and files that are cleaned if only some of
+    /// the categories are, respecting the project's pin.
+    fn selected_ephemeral_paths(&self) -> Vec<&EphemeralPath> {
+        if self.is_pinned_entirely() {
+            return Vec::new();
+        }
+        self.ephemeral_paths
+            .iter()
+            .filter(|path| path.category.is_some() && self.categories.selects(path.category))
+            .collect()
     }
+
+    /// Whether the project is pinned with the "all" scope, so nothing is
+    /// cleaned; the "dependencies" scope is part of the
+    /// [`Project::categories`].
+    pub fn is_pinned_entirely(&self) -> bool {
+        matches!(
+            self.pinned.as_ref().map(|keep| keep.scope),
+            Some(KeepScope::All)
+        )
+    }
+
+    /// How much space can potentially be freed up by cleaning this project.
+    ///
+    /// Respects the project's pin, if any, and the categories cleaned.
+    pub fn freeable_bytes(&self) -> u64 {
+        self.freeable_bytes
+    }
+
+    /// The total size of the files that would be removed by cleaning this
+    /// project; see [`DiskUsage`].
+    ///
+    /// Respects the project's pin, if any, and the categories cleaned.
+    pub fn apparent_bytes(&self) -> u64 {
+        self.apparent_bytes
+    }
+
+    /// Looks at the project's files again and asks the build tools for their
+    /// status, e.g., after cleaning.
+    pub fn refresh_status(&mut self) {
+        let scan = ProjectScan::new(&self.path, &self.ephemeral_dirs(), &Hardlinks::default());
+        self.status = status_from_scan(&self.path, &self.build_tools, &scan);
+        self.ephemeral_paths = ephemeral_paths_from(&self.build_tools, &scan);
+        self.ephemeral_disk_usage = scan.ephemeral;
+        self.nested_repositories = scan.nested_repositories;
+        self.errors = scan.errors;
+        self.symlinked_ephemeral_dirs = scan.symlinked_ephemeral_dirs;
+        self.ephemeral_mount_points = scan.ephemeral_mount_points;
+        self.update_sizes();
+    }
+
+    fn update_sizes(&mut self) {
+        if self.categories.is_active() || self.is_pinned_entirely() {
+            let mut usage = DiskUsage::default();
+            for path in self.selected_ephemeral_paths() {
+                usage += path.usage;
+            }
+            self.freeable_bytes = usage.freeable_bytes;
+            self.apparent_bytes = usage.apparent_bytes;
+            return;
+        }
+
+        let sizes = |status: &BuildStatus| match status {
+            BuildStatus::Built {
+                freeable_bytes,
+                apparent_bytes,
+            } => (*freeable_bytes, *apparent_bytes),
+            _ => (0, 0),
+        };
+        let sizes: Vec<(u64, u64)> = self.status.iter().map(sizes).collect();
+        self.freeable_bytes = sizes.iter().map(|(freeable, _)| freeable).sum();
+        self.apparent_bytes = sizes.iter().map(|(_, apparent)| apparent).sum();
+    }
+}
+
+fn ephemeral_dirs_of(build_tools: &[Box<dyn BuildTool>]) -> Vec<PathBuf> {
+    build_tools
+        .iter()
+        .flat_map(|x| x.ephemeral_dirs())
+        .collect()
+}
+
+/// What cleaning the project would remove, according to each build tool;
+/// paths reported by several build tools are listed once.
+fn ephemeral_paths_from(
+    build_tools: &[Box<dyn BuildTool>],
+    scan: &ProjectScan,
+) -> Vec<EphemeralPath> {
+    let mut paths: Vec<EphemeralPath> = Vec::new();
+    for path in build_tools
+        .iter()
+        .flat_map(|tool| tool.ephemeral_paths(scan))
+    {
+        if !paths.iter().any(|known| known.path == path.path) {
+            paths.push(path);
+        }
+    }
+    paths
+}
+
+/// Derives the status of each build tool from a single traversal of the
+/// project.
+fn status_from_scan(
+    path: &Path,
+    build_tools: &[Box<dyn BuildTool>],
+    scan: &ProjectScan,
+) -> Vec<BuildStatus> {
+    build_tools
+        .iter()
+        .map(|tool| {
+            tool.status_from_scan(scan).unwrap_or_else(|e| {
+                warn!("Failed to determine the status of {tool} for {path:?}: {e}");
+                BuildStatus::Unknown
+            })
+        })
+        .collect()
 }
 
 fn project_name_from(path: &Path, build_tools: &[Box<dyn BuildTool>]) -> anyhow::Result<String> {
@@ -135,7 +517,7 @@ fn project_name_from(path: &Path, build_tools: &[Box<dyn BuildTool>]) -> anyhow:
     }
     // None of the build tools knows the project's name, so let's use the
     // directory name as a fallback
-    let dirname = path.components().last().ok_or_else(|| {
+    let dirname = path.components().next_back().ok_or_else(|| {
         format_err!(
             "Could not determine project name: Could not determine the directory name of {path:?}"
         )
@@ -168,6 +550,59 @@ pub struct ProjectFilter {
     pub min_stale: Duration,
     /// Projects that don't satisfy the status filter are ignored
     pub status: StatusFilter,
+    /// Directories matching any of these patterns are not searched
+    pub exclude: GlobSet,
+    /// Directories deeper than this below the search root are not searched
+    pub max_depth: Option<usize>,
+    /// Don't descend into directories on other file systems
+    pub one_file_system: bool,
+    /// Follow symbolic links to directories
+    pub follow_symlinks: bool,
+    /// Also search hidden directories
+    pub hidden: bool,
+    /// Also search directories that are ignored by `.gitignore` and similar
+    /// files
+    pub no_ignore: bool,
+    /// Projects that are pinned in addition to those with a keep file
+    pub keep: Vec<Keep>,
+    /// Which categories of ephemeral directories are cleaned
+    pub categories: CategoryFilter,
+}
+
+/// Selects the ephemeral directories to clean by their [`Category`].
+#[derive(Debug, Clone, Default)]
+pub struct CategoryFilter {
+    /// If not empty, only these categories are cleaned.
+    pub only: Vec<Category>,
+    /// These categories are not cleaned.
+    pub except: Vec<Category>,
+}
+
+impl CategoryFilter {
+    /// The same filter, but without cleaning `category`.
+    pub fn except(mut self, category: Category) -> Self {
+        if !self.except.contains(&category) {
+            self.except.push(category);
+        }
+        self
+    }
+
+    /// Whether only some of the categories are cleaned.
+    pub fn is_active(&self) -> bool {
+        !self.only.is_empty() || !self.except.is_empty()
+    }
+
+    /// Whether directories of the given category are cleaned. Uncategorized
+    /// directories are only cleaned if all categories are.
+    pub fn selects(&self, category: Option<Category>) -> bool {
+        match category {
+            Some(category) => {
+                (self.only.is_empty() || self.only.contains(&category))
+                    && !self.except.contains(&category)
+            }
+            None => !self.is_active(),
+        }
+    }
 }
 
 /// Filter by status reported by the [`Project`]'s build tools.
@@ -178,3 +613,182 @@ pub enum StatusFilter {
     /// All projects that are not already clean are considered
     ExceptClean,
 }
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fmt,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use assert_fs::{
+        fixture::{FileWriteStr, PathChild},
+        TempDir,
+    };
+    use claim::assert_matches;
+    use globset::GlobSet;
+
+    use super::*;
+    use crate::build_tools::{CleanStep, Removal, Remover};
+
+    #[derive(Debug)]
+    struct CountingTool {
+        status_calls: Arc<AtomicUsize>,
+    }
+    impl BuildTool for CountingTool {
+        fn clean_plan(&self, _: Removal) -> anyhow::Result<Vec<CleanStep>> {
+            Ok(Vec::new())
+        }
+
+        fn status(&self) -> anyhow::Result<BuildStatus> {
+            self.status_calls.fetch_add(1, Ordering::SeqCst);
+            Ok(BuildStatus::Built {
+                freeable_bytes: 42,
+                apparent_bytes: 40,
+            })
+        }
+    }
+    impl fmt::Display for CountingTool {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "Counting")
+        }
+    }
+
+    /// Cleans by running `make clean`, which removes `out` and `generated`.
+    #[derive(Debug)]
+    struct MakeTool {
+        dir: PathBuf,
+    }
+    impl BuildTool for MakeTool {
+        fn clean_plan(&self, _: Removal) -> anyhow::Result<Vec<CleanStep>> {
+            Ok(vec![CleanStep::run(
+                "make",
+                &["clean".to_owned()],
+                &self.dir,
+                self.ephemeral_dirs(),
+            )])
+        }
+
+        fn ephemeral_dirs(&self) -> Vec<PathBuf> {
+            vec![self.dir.join("out"), self.dir.join("generated")]
+        }
+    }
+    impl fmt::Display for MakeTool {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "Make")
+        }
+    }
+
+    #[test]
+    fn commands_that_would_remove_paths_to_keep_are_replaced() {
+        let root = TempDir::new().unwrap();
+        let dir = root.path().canonicalize().unwrap();
+        git2::Repository::init(&dir).unwrap();
+        root.child(".gitignore").write_str("/out/\n").unwrap();
+        root.child("out/artifact").write_str("").unwrap();
+        // Not ignored, so it's not a mere build artifact
+        root.child("generated/source").write_str("").unwrap();
+        let project_filter = ProjectFilter {
+            min_stale: Duration::ZERO,
+            status: StatusFilter::Any,
+            exclude: GlobSet::empty(),
+            max_depth: None,
+            one_file_system: false,
+            follow_symlinks: false,
+            hidden: false,
+            no_ignore: false,
+            keep: Vec::new(),
+            categories: CategoryFilter::default(),
+        };
+
+        let project = Project::from_build_tools(
+            &dir,
+            vec![Box::new(MakeTool { dir: dir.clone() })],
+            &project_filter,
+            &ScanIndex::disabled(),
+            &Hardlinks::default(),
+        )
+        .unwrap()
+        .unwrap();
+        let plan = project.clean_plan(Removal::Delete).unwrap();
+
+        assert_eq!(plan.len(), 2);
+        assert_matches!(&plan[0], CleanStep::Remove { path, .. } if *path == dir.join("out"));
+        assert_matches!(&plan[1], CleanStep::Keep { path, .. } if *path == dir.join("generated"));
+    }
+
+    #[test]
+    fn uncategorized_directories_are_only_selected_with_all_categories() {
+        let all = CategoryFilter::default();
+        assert!(all.selects(Some(Category::Dependencies)));
+        assert!(all.selects(None));
+
+        let only = CategoryFilter {
+            only: vec![Category::BuildOutputs, Category::Cache],
+            except: Vec::new(),
+        };
+        assert!(only.selects(Some(Category::Cache)));
+        assert!(!only.selects(Some(Category::Dependencies)));
+        assert!(!only.selects(None));
+
+        let except = CategoryFilter {
+            only: Vec::new(),
+            except: vec![Category::Dependencies],
+        };
+        assert!(except.selects(Some(Category::Tooling)));
+        assert!(!except.selects(Some(Category::Dependencies)));
+        assert!(!except.selects(None));
+    }
+
+    #[test]
+    fn build_status_is_determined_once_until_refreshed() {
+        let root = TempDir::new().unwrap();
+        root.child("projectfile").write_str("").unwrap();
+        let status_calls = Arc::new(AtomicUsize::new(0));
+        let build_tools: Vec<Box<dyn BuildTool>> = vec![Box::new(CountingTool {
+            status_calls: Arc::clone(&status_calls),
+        })];
+        let project_filter = ProjectFilter {
+            min_stale: Duration::ZERO,
+            status: StatusFilter::ExceptClean,
+            exclude: GlobSet::empty(),
+            max_depth: None,
+            one_file_system: false,
+            follow_symlinks: false,
+            hidden: false,
+            no_ignore: false,
+            keep: Vec::new(),
+            categories: CategoryFilter::default(),
+        };
+
+        let mut project = Project::from_build_tools(
+            root.path(),
+            build_tools,
+            &project_filter,
+            &ScanIndex::disabled(),
+            &Hardlinks::default(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(project.freeable_bytes(), 42);
+        assert_eq!(project.apparent_bytes(), 40);
+        assert!(matches!(
+            project.status(),
+            [BuildStatus::Built {
+                freeable_bytes: 42,
+                ..
+            }]
+        ));
+        assert_eq!(status_calls.load(Ordering::SeqCst), 1);
+
+        // Cleaning doesn't look at the project again:
+        project.clean(&Remover::new(Removal::Delete)).unwrap();
+        assert_eq!(status_calls.load(Ordering::SeqCst), 1);
+
+        project.refresh_status();
+        assert_eq!(status_calls.load(Ordering::SeqCst), 2);
+    }
+}
@@ -1,20 +1,6 @@
-use ignore::WalkBuilder;
-use std::{borrow::Cow, path::Path};
+use std::borrow::Cow;
 use time::{Duration, OffsetDateTime};
 
-pub(super) fn dir_mtime(path: &Path) -> Option<OffsetDateTime> {
-    WalkBuilder::new(path)
-        .standard_filters(true)
-        .hidden(false)
-        .build()
-        .filter_map(|entry| entry.ok())
-        .filter_map(|entry| entry.metadata().ok())
-        .filter(|metadata| metadata.is_file())
-        .filter_map(|metadata| metadata.modified().ok())
-        .map(OffsetDateTime::from)
-        .max()
-}
-
 pub(crate) trait HumanReadableElapsed {
     fn human_readable_elapsed(&self) -> Cow<'static, str>;
 }
@@ -25,6 +11,8 @@ impl HumanReadableElapsed for OffsetDateTime {
     }
 }
 
+// The guards are kept uniform across all arms for readability.
+#[allow(unknown_lints, clippy::redundant_guards)]
 fn human_readable_elapsed(since: OffsetDateTime, now: OffsetDateTime) -> Cow<'static, str> {
     static HOUR: i64 = 60;
     static DAY: i64 = 24 * HOUR;
@@ -0,0 +1,497 @@
+//! Looks at all files of a project in a single pass.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Match,
+};
+use time::OffsetDateTime;
+use walkdir::WalkDir;
+
+use crate::{
+    build_tools::BuildStatus,
+    fs::{device_id, DiskUsage, Hardlinks, UsageCounter},
+};
+
+/// What a single traversal of a project directory found out.
+#[derive(Debug, Clone, Default)]
+pub struct ProjectScan {
+    /// The modification time of the most recently modified file, not
+    /// counting ephemeral directories, nested repositories and files ignored
+    /// by `.gitignore`.
+    pub mtime: Option<OffsetDateTime>,
+    /// The disk usage of each existing ephemeral directory (or file).
+    ///
+    /// Ephemeral directories that are symbolic links, or that contain mount
+    /// points, are not included, as they are not removed.
+    pub ephemeral: BTreeMap<PathBuf, DiskUsage>,
+    /// Ephemeral directories that are symbolic links to directories.
+    /// Cleaning only removes the link.
+    pub symlinked_ephemeral_dirs: Vec<PathBuf>,
+    /// Mount points at or within ephemeral directories, which are not
+    /// descended into.
+    pub ephemeral_mount_points: Vec<PathBuf>,
+    /// Directories within the project that are Git repositories of their
+    /// own, and thus typically separate projects. They are not descended
+    /// into.
+    pub nested_repositories: Vec<PathBuf>,
+    /// Files and directories that could not be read, e.g., due to missing
+    /// permissions. The other fields might be inaccurate if there are any.
+    pub errors: Vec<String>,
+}
+
+impl ProjectScan {
+    /// Traverses `project_dir` once.
+    ///
+    /// Ephemeral directories (and files) are measured instead of being
+    /// considered for the modification time. Ephemeral directories outside
+    /// of `project_dir` are ignored. Files with several hard links only count
+    /// as freeable if all of their links are within the ephemeral
+    /// directories, and if they aren't in `hardlinks` yet; see [`Hardlinks`].
+    pub fn new(project_dir: &Path, ephemeral_dirs: &[PathBuf], hardlinks: &Hardlinks) -> Self {
+        Self::walk(project_dir, ephemeral_dirs, None, hardlinks).unwrap_or_default()
+    }
+
+    /// Measures only the given ephemeral directories (and files), without
+    /// looking at the rest of the project, so the modification time is not
+    /// determined; e.g., to determine a build tool's status on its own.
+    pub fn of_ephemeral(ephemeral_dirs: &[PathBuf], hardlinks: &Hardlinks) -> Self {
+        let mut scan = ProjectScan::default();
+        let mut counters: BTreeMap<PathBuf, UsageCounter> = BTreeMap::new();
+        for dir in ephemeral_dirs {
+            let device = match fs::symlink_metadata(dir) {
+                Ok(metadata) if metadata.file_type().is_symlink() => {
+                    if dir.is_dir() {
+                        scan.symlinked_ephemeral_dirs.push(dir.clone());
+                    }
+                    continue;
+                }
+                Ok(metadata) => device_id(&metadata),
+                Err(_) => continue,
+            };
+            let counter = counters.entry(dir.clone()).or_default();
+            let mut walker = WalkDir::new(dir).into_iter();
+            while let Some(entry) = walker.next() {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        scan.errors.push(e.to_string());
+                        continue;
+                    }
+                };
+                match entry.metadata() {
+                    Ok(metadata) if metadata.is_dir() => {
+                        if device_id(&metadata) != device {
+                            scan.ephemeral_mount_points.push(entry.into_path());
+                            walker.skip_current_dir();
+                        }
+                    }
+                    Ok(metadata) => counter.add(&metadata),
+                    Err(e) => scan.errors.push(e.to_string()),
+                }
+            }
+        }
+        scan.ephemeral = finish_counters(counters, &scan.ephemeral_mount_points, hardlinks);
+        scan
+    }
+
+    /// Like [`ProjectScan::new`], but gives up as soon as a file is found
+    /// that was modified after `threshold`.
+    ///
+    /// Returns `None` in that case, as the project is too recent to be
+    /// considered, which can be decided without looking at the rest of it.
+    /// `hardlinks` is left untouched in that case.
+    pub fn unless_modified_after(
+        project_dir: &Path,
+        ephemeral_dirs: &[PathBuf],
+        threshold: OffsetDateTime,
+        hardlinks: &Hardlinks,
+    ) -> Option<Self> {
+        Self::walk(project_dir, ephemeral_dirs, Some(threshold), hardlinks)
+    }
+
+    fn walk(
+        project_dir: &Path,
+        ephemeral_dirs: &[PathBuf],
+        threshold: Option<OffsetDateTime>,
+        hardlinks: &Hardlinks,
+    ) -> Option<Self> {
+        let mut scan = ProjectScan::default();
+        let mut counters: BTreeMap<PathBuf, UsageCounter> = BTreeMap::new();
+        for dir in ephemeral_dirs
+            .iter()
+            .filter(|dir| dir.starts_with(project_dir))
+        {
+            match fs::symlink_metadata(dir) {
+                Ok(metadata) if metadata.file_type().is_symlink() => {
+                    if dir.is_dir() {
+                        scan.symlinked_ephemeral_dirs.push(dir.clone());
+                    }
+                }
+                Ok(_) => {
+                    counters.insert(dir.clone(), UsageCounter::default());
+                }
+                Err(_) => {}
+            }
+        }
+        let project_device = fs::metadata(project_dir)
+            .ok()
+            .and_then(|metadata| device_id(&metadata));
+
+        let mut ignores = IgnoreStack::for_project(project_dir);
+
+        let mut walker = WalkDir::new(project_dir).into_iter();
+        while let Some(entry) = walker.next() {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    scan.errors.push(e.to_string());
+                    continue;
+                }
+            };
+            let path = entry.path();
+            let is_dir = entry.file_type().is_dir();
+
+            if let Some(counter) = counters
+                .iter_mut()
+                .find(|(dir, _)| path.starts_with(dir))
+                .map(|(_, counter)| counter)
+            {
+                match entry.metadata() {
+                    Ok(metadata) if is_dir => {
+                        if device_id(&metadata) != project_device {
+                            scan.ephemeral_mount_points.push(path.to_owned());
+                            walker.skip_current_dir();
+                        }
+                    }
+                    Ok(metadata) => counter.add(&metadata),
+                    Err(e) => scan.errors.push(e.to_string()),
+                }
+                continue;
+            }
+            if scan.symlinked_ephemeral_dirs.iter().any(|dir| dir == path) {
+                continue;
+            }
+
+            ignores.leave_dirs_not_containing(path);
+            if entry.depth() > 0 && ignores.is_ignored(path, is_dir) {
+                if is_dir {
+                    walker.skip_current_dir();
+                }
+                continue;
+            }
+
+            if is_dir {
+                if entry.depth() > 0 && path.join(".git").exists() {
+                    scan.nested_repositories.push(path.to_owned());
+                    walker.skip_current_dir();
+                    continue;
+                }
+                ignores.enter_dir(path);
+            } else {
+                let mtime = match entry.metadata() {
+                    Ok(metadata) => metadata.modified().map(OffsetDateTime::from),
+                    Err(e) => {
+                        scan.errors.push(e.to_string());
+                        continue;
+                    }
+                };
+                let mtime = match mtime {
+                    Ok(mtime) => mtime,
+                    Err(e) => {
+                        scan.errors.push(format!("{}: {e}", path.display()));
+                        continue;
+                    }
+                };
+                if threshold.map_or(false, |threshold| mtime > threshold) {
+                    return None;
+                }
+                scan.mtime = Some(scan.mtime.map_or(mtime, |newest| newest.max(mtime)));
+            }
+        }
+
+        scan.ephemeral = finish_counters(counters, &scan.ephemeral_mount_points, hardlinks);
+        Some(scan)
+    }
+
+    /// The status of a build tool that would remove the given directories.
+    pub fn status_of(&self, ephemeral_dirs: &[PathBuf]) -> BuildStatus {
+        let mut usage = DiskUsage::default();
+        for dir_usage in ephemeral_dirs
+            .iter()
+            .filter_map(|dir| self.ephemeral.get(dir))
+        {
+            usage += *dir_usage;
+        }
+        usage.into()
+    }
+}
+
+/// The disk usage of each ephemeral directory, leaving out directories that
+/// contain a mount point, as they are not removed.
+fn finish_counters(
+    counters: BTreeMap<PathBuf, UsageCounter>,
+    mount_points: &[PathBuf],
+    hardlinks: &Hardlinks,
+) -> BTreeMap<PathBuf, DiskUsage> {
+    let counters = counters
+        .into_iter()
+        .filter(|(dir, _)| {
+            !mount_points
+                .iter()
+                .any(|mount_point| mount_point.starts_with(dir))
+        })
+        .collect();
+    UsageCounter::finish_all(counters, hardlinks)
+}
+
+/// The `.gitignore` files that apply to the current directory, outermost
+/// first.
+struct IgnoreStack {
+    stack: Vec<(PathBuf, Gitignore)>,
+}
+
+impl IgnoreStack {
+    /// Includes the `.gitignore` files of the project's parent directories,
+    /// up to the root of the enclosing Git repository.
+    fn for_project(project_dir: &Path) -> Self {
+        let mut parents = Vec::new();
+        if !project_dir.join(".git").exists() {
+            let mut is_within_repository = false;
+            for dir in project_dir.ancestors().skip(1) {
+                parents.push(dir);
+                if dir.join(".git").exists() {
+                    is_within_repository = true;
+                    break;
+                }
+            }
+            if !is_within_repository {
+                // Only the project's own files apply
+                parents.clear();
+            }
+        }
+
+        let mut ignores = IgnoreStack { stack: Vec::new() };
+        for dir in parents.into_iter().rev() {
+            ignores.enter_dir(dir);
+        }
+        ignores
+    }
+
+    fn enter_dir(&mut self, dir: &Path) {
+        let file = dir.join(".gitignore");
+        if !file.is_file() {
+            return;
+        }
+        let mut builder = GitignoreBuilder::new(dir);
+        // Invalid lines are skipped, like Git does
+        let _ = builder.add(&file);
+        if let Ok(gitignore) = builder.build() {
+            self.stack.push((dir.to_owned(), gitignore));
+        }
+    }
+
+    fn leave_dirs_not_containing(&mut self, path: &Path) {
+        while let Some((dir, _)) = self.stack.last() {
+            if path.starts_with(dir) && path != dir {
+                break;
+            }
+            self.stack.pop();
+        }
+    }
+
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        for (_, gitignore) in self.stack.iter().rev() {
+            match gitignore.matched(path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => continue,
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_fs::{
+        fixture::{FileWriteStr, PathChild, PathCreateDir},
+        TempDir,
+    };
+
+    use super::*;
+
+    #[test]
+    fn measures_ephemeral_dirs_and_ignores_them_for_the_mtime() {
+        let root = TempDir::new().unwrap();
+        root.child(".gitignore").write_str("/ignored/\n").unwrap();
+        root.child("src/main.rs").write_str("fn main() {}").unwrap();
+        root.child("ignored/file").write_str("ignored").unwrap();
+        root.child("target/debug/app").write_str("12345").unwrap();
+        root.child("target/debug/app.d").write_str("123").unwrap();
+        root.child("vendored/.git/HEAD").write_str("ref").unwrap();
+
+        let target = root.child("target").to_path_buf();
+        let missing = root.child("node_modules").to_path_buf();
+        let scan = ProjectScan::new(
+            root.path(),
+            &[target.clone(), missing],
+            &Hardlinks::default(),
+        );
+
+        let usage = scan.ephemeral.get(&target).unwrap();
+        assert_eq!(usage.apparent_bytes, 8);
+        assert_eq!(usage.files, 2);
+        assert_eq!(scan.ephemeral.len(), 1);
+        assert_eq!(
+            scan.nested_repositories,
+            vec![root.child("vendored").to_path_buf()]
+        );
+        assert!(matches!(
+            scan.status_of(&[target]),
+            BuildStatus::Built {
+                apparent_bytes: 8,
+                ..
+            }
+        ));
+
+        // Touching ephemeral, ignored or nested files doesn't make the
+        // project newer
+        let mtime = scan.mtime.unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        root.child("target/debug/app").write_str("123456").unwrap();
+        root.child("ignored/file").write_str("changed").unwrap();
+        root.child("vendored/lib.rs").write_str("").unwrap();
+        let ephemeral = [root.child("target").to_path_buf()];
+        let scan = ProjectScan::new(root.path(), &ephemeral, &Hardlinks::default());
+        assert_eq!(scan.mtime, Some(mtime));
+
+        root.child("src/main.rs")
+            .write_str("fn main() { }")
+            .unwrap();
+        let scan = ProjectScan::new(root.path(), &ephemeral, &Hardlinks::default());
+        assert!(scan.mtime.unwrap() > mtime);
+    }
+
+    #[test]
+    fn gives_up_on_files_modified_after_the_threshold() {
+        let root = TempDir::new().unwrap();
+        root.child("src/main.rs").write_str("fn main() {}").unwrap();
+        root.child("target/debug/app").write_str("12345").unwrap();
+        let ephemeral = [root.child("target").to_path_buf()];
+        let hardlinks = Hardlinks::default();
+        let mtime = ProjectScan::new(root.path(), &ephemeral, &hardlinks)
+            .mtime
+            .unwrap();
+
+        let scan = ProjectScan::unless_modified_after(
+            root.path(),
+            &ephemeral,
+            mtime - time::Duration::SECOND,
+            &hardlinks,
+        );
+        assert!(scan.is_none());
+
+        // Ephemeral directories don't count
+        let scan = ProjectScan::unless_modified_after(root.path(), &ephemeral, mtime, &hardlinks)
+            .expect("not modified after its own mtime");
+        assert_eq!(scan.mtime, Some(mtime));
+        assert!(matches!(
+            scan.status_of(&ephemeral),
+            BuildStatus::Built {
+                apparent_bytes: 5,
+                ..
+            }
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_ephemeral_dirs_are_not_measured() {
+        let root = TempDir::new().unwrap();
+        root.child("shared/node_modules/package.js")
+            .write_str("12345")
+            .unwrap();
+        root.child("project/package.json").write_str("{}").unwrap();
+        std::os::unix::fs::symlink(
+            root.child("shared/node_modules").path(),
+            root.child("project/node_modules").path(),
+        )
+        .unwrap();
+
+        let ephemeral = [root.child("project/node_modules").to_path_buf()];
+        let scan = ProjectScan::new(
+            root.child("project").path(),
+            &ephemeral,
+            &Hardlinks::default(),
+        );
+        assert_eq!(scan.symlinked_ephemeral_dirs, ephemeral.to_vec());
+        assert!(scan.ephemeral.is_empty());
+        assert!(matches!(scan.status_of(&ephemeral), BuildStatus::Clean));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hard_linked_files_are_only_freeable_if_all_links_are_removed() {
+        let root = TempDir::new().unwrap();
+        let content = "x".repeat(10_000);
+        root.child("store/package.js")
+            .write_str(content.as_str())
+            .unwrap();
+        for project in ["a", "b"] {
+            root.child(project)
+                .child("package.json")
+                .write_str("{}")
+                .unwrap();
+            let modules = root.child(project).child("node_modules");
+            modules.create_dir_all().unwrap();
+            std::fs::hard_link(
+                root.child("store/package.js").path(),
+                modules.child("package.js").path(),
+            )
+            .unwrap();
+        }
+
+        let hardlinks = Hardlinks::default();
+        let usage_of = |project: &str| {
+            let ephemeral = [root.child(project).child("node_modules").to_path_buf()];
+            let scan = ProjectScan::new(root.child(project).path(), &ephemeral, &hardlinks);
+            scan.ephemeral[&ephemeral[0]]
+        };
+
+        // The store outside of the projects keeps the file
+        for project in ["a", "b"] {
+            let usage = usage_of(project);
+            assert_eq!(usage.apparent_bytes, 10_000);
+            assert_eq!(usage.freeable_bytes, 0);
+        }
+
+        // Links within the same project are freeable, even across ephemeral
+        // directories, but only once
+        let c = root.child("c");
+        c.child("target/lib.js")
+            .write_str(content.as_str())
+            .unwrap();
+        c.child("node_modules").create_dir_all().unwrap();
+        std::fs::hard_link(
+            c.child("target/lib.js").path(),
+            c.child("node_modules/lib.js").path(),
+        )
+        .unwrap();
+        let ephemeral = [
+            c.child("node_modules").to_path_buf(),
+            c.child("target").to_path_buf(),
+        ];
+        let scan = ProjectScan::new(c.path(), &ephemeral, &hardlinks);
+        let node_modules = scan.ephemeral[&ephemeral[0]];
+        let target = scan.ephemeral[&ephemeral[1]];
+        assert!(node_modules.freeable_bytes >= 10_000);
+        assert_eq!(target.freeable_bytes, 0);
+        assert_eq!(target.apparent_bytes, 10_000);
+    }
+}
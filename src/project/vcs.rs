@@ -1,5 +1,5 @@
 use anyhow::bail;
-use git2::Repository;
+use git2::{Repository, StatusOptions};
 use std::{
     fmt,
     path::{Path, PathBuf},
@@ -39,6 +39,18 @@ impl VersionControlSystem {
             Git(ref git) => git.root(),
         }
     }
+
+    /// The files at or below `path` that are tracked, or untracked but not
+    /// ignored.
+    ///
+    /// Such files are not merely build artifacts, so they shouldn't be
+    /// removed when cleaning a project.
+    pub fn unignored_files(&self, path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+        use VersionControlSystem::*;
+        match *self {
+            Git(ref git) => git.unignored_files(path),
+        }
+    }
 }
 
 pub struct Git {
@@ -66,6 +78,41 @@ impl Git {
     }
 }
 
+impl Git {
+    fn unignored_files(&self, path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+        let workdir = match self.repo.workdir() {
+            Some(workdir) => workdir.canonicalize()?,
+            None => return Ok(Vec::new()),
+        };
+        // A symbolic link is a file of its own, so it's not resolved
+        let path = match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => parent.canonicalize()?.join(name),
+            _ => path.canonicalize()?,
+        };
+        let relative_path = match path.strip_prefix(&workdir) {
+            Ok(relative_path) => relative_path,
+            Err(_) => return Ok(Vec::new()),
+        };
+
+        let mut options = StatusOptions::new();
+        options
+            .pathspec(relative_path)
+            .include_untracked(true)
+            .recurse_untracked_dirs(true)
+            .include_unmodified(true)
+            .include_ignored(false);
+        let statuses = self.repo.statuses(Some(&mut options))?;
+        let files = statuses
+            .iter()
+            .filter(|entry| !entry.status().is_ignored())
+            .filter_map(|entry| entry.path().map(|file| workdir.join(file)))
+            // The pathspec is a pattern, so it might match more than that
+            .filter(|file| file.starts_with(&path))
+            .collect();
+        Ok(files)
+    }
+}
+
 impl fmt::Debug for Git {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Git").field("root", &self.root()).finish()
@@ -103,4 +150,32 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn ignored_files_are_told_apart_from_tracked_and_untracked_ones() -> anyhow::Result<()> {
+        let root = TempDir::new()?;
+        root.child(".gitignore").write_str("/build/ignored\n")?;
+        root.child("build/ignored/output").write_str("")?;
+        root.child("build/tracked").write_str("")?;
+        root.child("build-other/file").write_str("")?;
+        let repo = Repository::init(root.path())?;
+        let mut index = repo.index()?;
+        index.add_path(Path::new("build/tracked"))?;
+        index.write()?;
+
+        let vcs = VersionControlSystem::try_from(root.path())?.unwrap();
+        assert_eq!(
+            vcs.unignored_files(root.child("build/ignored").path())?,
+            Vec::<PathBuf>::new()
+        );
+        assert_eq!(
+            vcs.unignored_files(root.child("build").path())?,
+            vec![path_of(&root.child("build/tracked"))]
+        );
+
+        root.child("build/untracked").write_str("")?;
+        assert_eq!(vcs.unignored_files(root.child("build").path())?.len(), 2);
+
+        Ok(())
+    }
 }
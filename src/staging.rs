@@ -0,0 +1,231 @@
+//! Staged deletion.
+//!
+//! Rather than being deleted right away, directories can be moved into a
+//! staging area on the same file system, which is just as quick, but can be
+//! undone. A later run deletes them for good, once their grace period is
+//! over; see [`purge_expired`].
+//!
+//! Directories on the same file system as the home staging area
+//! (`~/.local/share/makeclean/staged`, respecting `$XDG_DATA_HOME`) are
+//! moved there. Directories on other file systems are moved to
+//! `$topdir/.makeclean-staged-$uid` at the top of their mount point instead,
+//! as moving them to the home staging area would mean copying them. Staged
+//! directories are recorded in the [journal](crate::journal), which is also
+//! used to find them again.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+    fs::rename_to_available,
+    journal::{Action, JournalEntry},
+};
+
+/// How long staged directories are kept, unless configured otherwise.
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::weeks(1);
+
+/// Moves the file or directory at `path` into the staging area of its file
+/// system, returning its new location.
+#[cfg(unix)]
+pub fn stage(path: &Path) -> anyhow::Result<PathBuf> {
+    let home_staging = unix::home_staging_dir()
+        .context("Failed to locate the staging area: neither $XDG_DATA_HOME nor $HOME is set")?;
+    unix::stage(path, &home_staging)
+}
+
+/// Moves the file or directory at `path` into the staging area of its file
+/// system, returning its new location.
+#[cfg(not(unix))]
+pub fn stage(path: &Path) -> anyhow::Result<PathBuf> {
+    bail!(
+        "Failed to stage {} for deletion: not supported on this platform",
+        path.display()
+    )
+}
+
+/// Moves a staged file or directory back to `original_path`.
+pub fn restore(staged_path: &Path, original_path: &Path) -> anyhow::Result<()> {
+    if !staged_path.exists() {
+        bail!(
+            "{} is no longer staged; its grace period might be over",
+            staged_path.display()
+        );
+    }
+    if original_path.exists() {
+        bail!("{} already exists", original_path.display());
+    }
+    if let Some(parent) = original_path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    fs::rename(staged_path, original_path).with_context(|| {
+        format!(
+            "Failed to move {} to {}",
+            staged_path.display(),
+            original_path.display()
+        )
+    })
+}
+
+/// Deletes staged directories whose grace period is over.
+///
+/// The grace period is the one in force when a directory was staged, as
+/// recorded in its journal entry. Returns the journal entries of the deleted
+/// directories. A staging location that has been used more than once is only
+/// considered with respect to its most recent entry.
+pub fn purge_expired(entries: &[JournalEntry]) -> anyhow::Result<Vec<&JournalEntry>> {
+    let mut latest: HashMap<&Path, &JournalEntry> = HashMap::new();
+    for entry in entries.iter().filter(|entry| entry.action == Action::Stage) {
+        if let Some(staged_path) = entry.backup.as_deref() {
+            latest.insert(staged_path, entry);
+        }
+    }
+
+    let now = OffsetDateTime::now_utc();
+    let mut purged = Vec::new();
+    for (staged_path, entry) in latest {
+        let is_expired = entry.expires().map_or(false, |expires| expires < now);
+        if !is_expired || !staged_path.exists() {
+            continue;
+        }
+        let result = if staged_path.is_dir() {
+            fs::remove_dir_all(staged_path)
+        } else {
+            fs::remove_file(staged_path)
+        };
+        result.with_context(|| format!("Failed to delete {}", staged_path.display()))?;
+        purged.push(entry);
+    }
+    Ok(purged)
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        env, fs,
+        os::unix::fs::MetadataExt,
+        path::{Path, PathBuf},
+    };
+
+    use anyhow::{bail, Context};
+
+    use super::rename_to_available;
+    use crate::{
+        config::home_dir,
+        fs::unix::{create_owned_dir, device_of, mount_point},
+    };
+
+    pub(super) fn home_staging_dir() -> Option<PathBuf> {
+        let data_home = env::var_os("XDG_DATA_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| home_dir().map(|home| home.join(".local").join("share")))?;
+        Some(data_home.join("makeclean").join("staged"))
+    }
+
+    pub(super) fn stage(path: &Path, home_staging: &Path) -> anyhow::Result<PathBuf> {
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("Failed to stage {} for deletion", path.display()))?;
+        let file_name = match path.file_name() {
+            Some(file_name) => file_name.to_string_lossy().to_string(),
+            None => bail!("Refusing to stage {} for deletion", path.display()),
+        };
+        let device = fs::symlink_metadata(&path)?.dev();
+
+        // Renaming is only possible within the same file system
+        let staging_dir = if device_of(home_staging)? == device {
+            home_staging.to_owned()
+        } else {
+            // SAFETY: getuid is always successful and has no side effects
+            let uid = unsafe { libc::getuid() };
+            mount_point(&path, device).join(format!(".makeclean-staged-{uid}"))
+        };
+        create_owned_dir(&staging_dir)?;
+
+        let candidates = (1..).map(|i| staging_dir.join(format!("{file_name}~{i}")));
+        let staged_path =
+            rename_to_available(&path, candidates)?.expect("there's always another candidate");
+        Ok(staged_path)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use assert_fs::{
+        fixture::{FileWriteStr, PathChild},
+        TempDir,
+    };
+
+    use super::*;
+
+    #[test]
+    fn staged_directories_can_be_restored() {
+        let root = TempDir::new().unwrap();
+        let home_staging = root.child("staged");
+        root.child("a/target/debug/app").write_str("").unwrap();
+        root.child("b/target/debug/app").write_str("").unwrap();
+
+        let staged_a = unix::stage(root.child("a/target").path(), &home_staging).unwrap();
+        let staged_b = unix::stage(root.child("b/target").path(), &home_staging).unwrap();
+        assert_eq!(staged_a, home_staging.child("target~1").path());
+        assert_eq!(staged_b, home_staging.child("target~2").path());
+        assert!(!root.child("a/target").exists());
+
+        restore(&staged_a, root.child("a/target").path()).unwrap();
+        assert!(root.child("a/target/debug/app").path().is_file());
+        assert!(!staged_a.exists());
+
+        // Nothing is overwritten
+        root.child("b/target/new").write_str("").unwrap();
+        assert!(restore(&staged_b, root.child("b/target").path()).is_err());
+        assert!(staged_b.exists());
+    }
+
+    #[test]
+    fn only_directories_staged_before_the_grace_period_are_purged() {
+        let root = TempDir::new().unwrap();
+        root.child("old~1/file").write_str("").unwrap();
+        root.child("new~1/file").write_str("").unwrap();
+        root.child("long~1/file").write_str("").unwrap();
+        let entry = |name: &str, timestamp: &str, expires: Option<&str>| JournalEntry {
+            run_id: "run".to_owned(),
+            timestamp: timestamp.to_owned(),
+            project: root.to_path_buf(),
+            action: Action::Stage,
+            path: root.child(name).to_path_buf(),
+            bytes: 0,
+            backup: Some(root.child(format!("{name}~1")).to_path_buf()),
+            expires: expires.map(str::to_owned),
+        };
+        let rfc3339 = |time: OffsetDateTime| {
+            time.format(&time::format_description::well_known::Rfc3339)
+                .unwrap()
+        };
+        let now = OffsetDateTime::now_utc();
+        let entries = vec![
+            entry("old", "2022-01-01T00:00:00Z", None),
+            entry("new", "2022-01-01T00:00:00Z", None),
+            // Staged again since
+            entry("new", &rfc3339(now), None),
+            // Staged long ago, but with a longer grace period
+            entry(
+                "long",
+                "2022-01-01T00:00:00Z",
+                Some(&rfc3339(now + Duration::weeks(1))),
+            ),
+        ];
+
+        let purged = purge_expired(&entries).unwrap();
+        assert_eq!(purged, vec![&entries[0]]);
+        assert!(!root.child("old~1").exists());
+        assert!(root.child("new~1").exists());
+        assert!(root.child("long~1").exists());
+    }
+}
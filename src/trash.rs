@@ -0,0 +1,319 @@
+//! Moves files to the trash instead of deleting them.
+//!
+//! Implements the [freedesktop.org Trash specification], so trashed
+//! directories show up in (and can be restored by) the usual desktop tools.
+//! Files on the same file system as the home trash (`~/.local/share/Trash`,
+//! respecting `$XDG_DATA_HOME`) are moved there. Files on other file systems
+//! are moved to a trash directory at the top of their mount point instead,
+//! either `$topdir/.Trash/$uid` or `$topdir/.Trash-$uid`, as moving them to
+//! the home trash would mean copying them.
+//!
+//! [freedesktop.org Trash specification]: https://specifications.freedesktop.org/trash-spec/trashspec-latest.html
+
+use std::path::{Path, PathBuf};
+
+/// A file or directory that has been moved to the trash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trashed {
+    /// Where the file or directory was located before.
+    pub original_path: PathBuf,
+    /// Where the file or directory is located now.
+    pub trashed_path: PathBuf,
+    /// The `.trashinfo` file that describes where it came from.
+    pub info_path: PathBuf,
+}
+
+/// Moves the file or directory at `path` to the trash.
+#[cfg(unix)]
+pub fn trash(path: &Path) -> anyhow::Result<Trashed> {
+    use anyhow::Context;
+
+    let home_trash = unix::home_trash_dir()
+        .context("Failed to locate the trash: neither $XDG_DATA_HOME nor $HOME is set")?;
+    unix::trash(path, &home_trash)
+}
+
+/// Moves the file or directory at `path` to the trash.
+#[cfg(not(unix))]
+pub fn trash(path: &Path) -> anyhow::Result<Trashed> {
+    anyhow::bail!(
+        "Failed to move {} to the trash: not supported on this platform",
+        path.display()
+    )
+}
+
+/// Moves a file or directory that was moved to the trash back to
+/// `original_path`, and removes its `.trashinfo` file.
+pub fn restore(trashed_path: &Path, original_path: &Path) -> anyhow::Result<()> {
+    use anyhow::{bail, Context};
+    use std::fs;
+
+    if !trashed_path.exists() {
+        bail!("{} is no longer in the trash", trashed_path.display());
+    }
+    if original_path.exists() {
+        bail!("{} already exists", original_path.display());
+    }
+    if let Some(parent) = original_path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    fs::rename(trashed_path, original_path).with_context(|| {
+        format!(
+            "Failed to move {} to {}",
+            trashed_path.display(),
+            original_path.display()
+        )
+    })?;
+
+    // The info file is at `$trash/info/$name.trashinfo` for `$trash/files/$name`
+    let info_path = trashed_path
+        .parent()
+        .and_then(Path::parent)
+        .zip(trashed_path.file_name())
+        .map(|(trash_dir, name)| {
+            trash_dir
+                .join("info")
+                .join(format!("{}.trashinfo", name.to_string_lossy()))
+        });
+    if let Some(info_path) = info_path {
+        let _ = fs::remove_file(info_path);
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        env,
+        fs::{self, OpenOptions},
+        io::{self, Write},
+        os::unix::{ffi::OsStrExt, fs::MetadataExt},
+        path::{Path, PathBuf},
+    };
+
+    use anyhow::{bail, Context};
+    use time::{macros::format_description, OffsetDateTime};
+
+    use super::Trashed;
+    use crate::{
+        config::home_dir,
+        fs::unix::{create_owned_dir, create_private_dir_all, device_of, mount_point},
+    };
+
+    pub(super) fn home_trash_dir() -> Option<PathBuf> {
+        let data_home = env::var_os("XDG_DATA_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| home_dir().map(|home| home.join(".local").join("share")))?;
+        Some(data_home.join("Trash"))
+    }
+
+    pub(super) fn trash(path: &Path, home_trash: &Path) -> anyhow::Result<Trashed> {
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("Failed to move {} to the trash", path.display()))?;
+        let file_name = match path.file_name() {
+            Some(file_name) => file_name.to_owned(),
+            None => bail!("Refusing to move {} to the trash", path.display()),
+        };
+        let device = fs::symlink_metadata(&path)?.dev();
+
+        // Within its own trash directory, the original location is recorded
+        // relative to the mount point
+        let (trash_dir, recorded_path) = if device_of(home_trash)? == device {
+            (home_trash.to_owned(), path.clone())
+        } else {
+            let topdir = mount_point(&path, device);
+            let recorded_path = path.strip_prefix(&topdir).expect("below its mount point");
+            (topdir_trash_dir(&topdir)?, recorded_path.to_owned())
+        };
+
+        let files_dir = trash_dir.join("files");
+        let info_dir = trash_dir.join("info");
+        for dir in [&files_dir, &info_dir] {
+            create_private_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+
+        // Creating the info file first reserves the name, as required by the
+        // specification
+        let info = trash_info(&recorded_path);
+        let mut n = 1;
+        let (trashed_path, info_path) = loop {
+            let name = match n {
+                1 => file_name.to_string_lossy().to_string(),
+                n => format!("{}.{n}", file_name.to_string_lossy()),
+            };
+            let trashed_path = files_dir.join(&name);
+            let info_path = info_dir.join(format!("{name}.trashinfo"));
+            n += 1;
+            if trashed_path.exists() {
+                continue;
+            }
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&info_path)
+            {
+                Ok(mut file) => {
+                    file.write_all(info.as_bytes())?;
+                    break (trashed_path, info_path);
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => {
+                    return Err(e)
+                        .with_context(|| format!("Failed to create {}", info_path.display()))
+                }
+            }
+        };
+
+        if let Err(e) = fs::rename(&path, &trashed_path) {
+            let _ = fs::remove_file(&info_path);
+            return Err(e).with_context(|| {
+                format!(
+                    "Failed to move {} to {}",
+                    path.display(),
+                    trashed_path.display()
+                )
+            });
+        }
+
+        Ok(Trashed {
+            original_path: path,
+            trashed_path,
+            info_path,
+        })
+    }
+
+    fn trash_info(recorded_path: &Path) -> String {
+        let deletion_date = OffsetDateTime::now_local()
+            .unwrap_or_else(|_| OffsetDateTime::now_utc())
+            .format(format_description!(
+                "[year]-[month]-[day]T[hour]:[minute]:[second]"
+            ))
+            .expect("can format the deletion date");
+        format!(
+            "[Trash Info]\nPath={}\nDeletionDate={deletion_date}\n",
+            percent_encode(recorded_path)
+        )
+    }
+
+    /// Encodes the path like in a URL, as required for the `Path` key.
+    pub(super) fn percent_encode(path: &Path) -> String {
+        let mut encoded = String::new();
+        for &byte in path.as_os_str().as_bytes() {
+            match byte {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => encoded.push(byte as char),
+                b'/' | b'-' | b'_' | b'.' | b'!' | b'~' | b'*' | b'\'' | b'(' | b')' => {
+                    encoded.push(byte as char)
+                }
+                _ => encoded.push_str(&format!("%{byte:02X}")),
+            }
+        }
+        encoded
+    }
+
+    fn topdir_trash_dir(topdir: &Path) -> anyhow::Result<PathBuf> {
+        // SAFETY: getuid is always successful and has no side effects
+        let uid = unsafe { libc::getuid() };
+
+        // An administrator may have set up a shared trash directory, which
+        // must have the sticky bit set and must not be a symbolic link
+        let shared = topdir.join(".Trash");
+        if let Ok(metadata) = fs::symlink_metadata(&shared) {
+            let is_sticky = metadata.mode() & 0o1000 != 0;
+            if metadata.is_dir() && is_sticky {
+                let dir = shared.join(uid.to_string());
+                if create_private_dir_all(&dir).is_ok() {
+                    return Ok(dir);
+                }
+            }
+        }
+
+        let dir = topdir.join(format!(".Trash-{uid}"));
+        create_owned_dir(&dir)?;
+        Ok(dir)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use std::{fs, path::Path};
+
+    use assert_fs::{
+        fixture::{FileWriteStr, PathChild},
+        TempDir,
+    };
+
+    use super::{
+        restore,
+        unix::{percent_encode, trash},
+    };
+
+    #[test]
+    fn trashed_directories_are_described_by_a_trashinfo_file() {
+        let root = TempDir::new().unwrap();
+        let home_trash = TempDir::new().unwrap();
+        root.child("project/target/debug/app")
+            .write_str("")
+            .unwrap();
+        root.child("other/target/debug/app").write_str("").unwrap();
+
+        let trashed = trash(root.child("project/target").path(), home_trash.path()).unwrap();
+        assert!(!root.child("project/target").exists());
+        assert_eq!(
+            trashed.trashed_path,
+            home_trash.child("files/target").path()
+        );
+        assert!(trashed.trashed_path.join("debug/app").is_file());
+        let info = fs::read_to_string(&trashed.info_path).unwrap();
+        assert!(info.starts_with("[Trash Info]\n"));
+        assert!(info.contains(&format!(
+            "\nPath={}\n",
+            percent_encode(&trashed.original_path)
+        )));
+        assert!(info.contains("\nDeletionDate="));
+
+        // Names are not reused
+        let trashed = trash(root.child("other/target").path(), home_trash.path()).unwrap();
+        assert_eq!(
+            trashed.trashed_path,
+            home_trash.child("files/target.2").path()
+        );
+        assert_eq!(
+            trashed.info_path,
+            home_trash.child("info/target.2.trashinfo").path()
+        );
+    }
+
+    #[test]
+    fn restoring_removes_the_trashinfo_file() {
+        let root = TempDir::new().unwrap();
+        let home_trash = TempDir::new().unwrap();
+        root.child("project/target/debug/app")
+            .write_str("")
+            .unwrap();
+
+        let trashed = trash(root.child("project/target").path(), home_trash.path()).unwrap();
+        restore(&trashed.trashed_path, &trashed.original_path).unwrap();
+        assert!(root.child("project/target/debug/app").path().is_file());
+        assert!(!trashed.trashed_path.exists());
+        assert!(!trashed.info_path.exists());
+
+        // Nothing is overwritten
+        let trashed = trash(root.child("project/target").path(), home_trash.path()).unwrap();
+        root.child("project/target/new").write_str("").unwrap();
+        assert!(restore(&trashed.trashed_path, &trashed.original_path).is_err());
+        assert!(trashed.trashed_path.exists());
+    }
+
+    #[test]
+    fn paths_are_percent_encoded() {
+        assert_eq!(
+            percent_encode(Path::new("/home/user/my project/node_modules")),
+            "/home/user/my%20project/node_modules"
+        );
+        assert_eq!(percent_encode(Path::new("/tmp/ä")), "/tmp/%C3%A4");
+    }
+}
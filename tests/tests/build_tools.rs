@@ -5,7 +5,7 @@ use std::process::Command;
 use anyhow::{Context, Result};
 use assert_cmd::prelude::CommandCargoExt;
 use assert_fs::{
-    fixture::{ChildPath, PathChild},
+    fixture::{ChildPath, PathChild, PathCreateDir},
     TempDir,
 };
 use makeclean::project::dto::ProjectDto;
@@ -82,3 +82,109 @@ fn ignores_npm_projects_within_node_modules() -> Result<()> {
     root.close()?;
     Ok(())
 }
+
+#[test]
+fn ignores_npm_projects_within_node_modules_that_belong_to_no_project() -> Result<()> {
+    let root = TempDir::new()?;
+    let dep_dir = root.child("node_modules/the_dependency");
+    npm_init(&dep_dir).with_context(|| "failed to init dependency project within node_modules")?;
+    dep_dir
+        .child("node_modules/nested_dependency")
+        .create_dir_all()?;
+
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--list", "--json"])
+        .arg(root.path())
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    let output = String::from_utf8(output.stdout)?;
+
+    assert!(
+        output.trim().is_empty(),
+        "Expected no projects, got: {output:?}"
+    );
+
+    root.close()?;
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn recognizes_projects_using_probe_plugins_on_path() -> Result<()> {
+    use assert_fs::fixture::FileWriteStr;
+    use std::{env, fs, os::unix::fs::PermissionsExt};
+
+    let plugin_dir = TempDir::new()?;
+    let plugin = plugin_dir.child("makeclean-probe-acme");
+    plugin.write_str(
+        r#"#!/bin/sh
+dir=$(sed -n 's/.*"dir":"\([^"]*\)".*/\1/p')
+if [ -f "$dir/acme.build" ]; then
+  echo '{"tool": "Acme", "project_name": "acme-project", "freeable_bytes": 42}'
+fi
+"#,
+    )?;
+    fs::set_permissions(&plugin, fs::Permissions::from_mode(0o755))?;
+    let path = env::join_paths(
+        std::iter::once(plugin_dir.to_path_buf())
+            .chain(env::split_paths(&env::var_os("PATH").unwrap())),
+    )?;
+
+    let root = TempDir::new()?;
+    let project_dir = root.child("acme_project");
+    project_dir.child("acme.build").write_str("")?;
+    cargo_init(&root.child("cargo_project"))?;
+
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--list", "--json", "--type", "acme"])
+        .env("PATH", path)
+        .current_dir(&root)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    let output = String::from_utf8(output.stdout)?;
+
+    // We expect a single line/project
+    let project: ProjectDto = serde_json::from_str(output.trim())
+        .with_context(|| format!("failed to deserialize '{}'", output.trim()))?;
+    assert_eq!(project.path, canonicalized_str(&project_dir));
+    assert_eq!(project.name, "acme-project");
+    assert_eq!(project.build_tools, vec!["Acme"]);
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn probe_plugins_that_dont_respond_are_killed() -> Result<()> {
+    use assert_fs::fixture::FileWriteStr;
+    use std::{
+        env, fs,
+        os::unix::fs::PermissionsExt,
+        time::{Duration, Instant},
+    };
+
+    let plugin_dir = TempDir::new()?;
+    let plugin = plugin_dir.child("makeclean-probe-slow");
+    plugin.write_str("#!/bin/sh\nexec sleep 60\n")?;
+    fs::set_permissions(&plugin, fs::Permissions::from_mode(0o755))?;
+    let path = env::join_paths(
+        std::iter::once(plugin_dir.to_path_buf())
+            .chain(env::split_paths(&env::var_os("PATH").unwrap())),
+    )?;
+
+    let root = TempDir::new()?;
+    let started = Instant::now();
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--list", "--json", "--type", "slow"])
+        .env("PATH", path)
+        .current_dir(&root)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)?.trim().is_empty());
+    assert!(started.elapsed() < Duration::from_secs(30));
+
+    Ok(())
+}
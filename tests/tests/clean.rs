@@ -1,9 +1,20 @@
 use anyhow::Result;
 use assert_cmd::prelude::CommandCargoExt;
-use assert_fs::{fixture::PathChild, TempDir};
+use assert_fs::{
+    fixture::{FileWriteStr, PathChild},
+    TempDir,
+};
+use makeclean::{
+    build_tools::{Category, CleanStep},
+    project::dto::ProjectDto,
+};
 use std::process::Command;
 
-use crate::util::cargo::{cargo_build, cargo_init};
+use crate::util::{
+    cargo::{cargo_build, cargo_init},
+    git::git_init,
+    npm::npm_init,
+};
 
 #[test]
 fn the_prompt_only_lists_projects_that_need_cleaning() -> Result<()> {
@@ -135,3 +146,453 @@ fn cleaning_a_cleaned_project_is_a_noop() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn pinned_projects_are_not_cleaned() -> Result<()> {
+    let root = TempDir::new()?;
+    let config_home = TempDir::new()?;
+
+    let pinned_by_file = root.child("pinned-by-file");
+    cargo_init(&pinned_by_file)?;
+    cargo_build(&pinned_by_file)?;
+    pinned_by_file
+        .child(".makeclean-keep")
+        .write_str(r#"reason = "demo""#)?;
+
+    let pinned_by_config = root.child("pinned-by-config");
+    cargo_init(&pinned_by_config)?;
+    cargo_build(&pinned_by_config)?;
+    root.child(".makeclean.toml").write_str(
+        r#"
+        [[keep]]
+        path = "pinned-by-config"
+        "#,
+    )?;
+
+    let expired = root.child("expired");
+    cargo_init(&expired)?;
+    cargo_build(&expired)?;
+    expired
+        .child(".makeclean-keep")
+        .write_str("until = 2000-01-01")?;
+
+    // Pinned projects are listed as such:
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--list", "--json"])
+        .env("XDG_CONFIG_HOME", config_home.path())
+        .current_dir(&root)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    let projects: Vec<ProjectDto> = String::from_utf8(output.stdout)?
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(projects.len(), 3);
+    for project in projects {
+        let is_pinned = !project.path.ends_with("expired");
+        assert_eq!(project.pinned.is_some(), is_pinned, "{}", project.path);
+    }
+
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--min-stale", "0", "--type", "cargo", "--yes"])
+        .env("XDG_CONFIG_HOME", config_home.path())
+        .current_dir(&root)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout)?;
+
+    // Projects that are pinned entirely are not offered for cleaning, but
+    // reported as kept:
+    let (offered, summary) = stdout.split_once("SUMMARY:").unwrap();
+    assert!(offered.contains("expired"));
+    assert!(!offered.contains("pinned-by"));
+    assert!(summary.contains("1 project cleaned"));
+    assert!(summary.contains("2 pinned projects kept"));
+
+    assert!(pinned_by_file.child("target").path().exists());
+    assert!(pinned_by_config.child("target").path().exists());
+    assert!(!expired.child("target").path().exists());
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn projects_with_unreadable_directories_are_only_cleaned_if_forced() -> Result<()> {
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    let root = TempDir::new()?;
+    let project_dir = root.child("project");
+    cargo_init(&project_dir)?;
+    cargo_build(&project_dir)?;
+    let secret = project_dir.child("secret");
+    secret.child("notes.txt").write_str("recently changed")?;
+    fs::set_permissions(secret.path(), fs::Permissions::from_mode(0o000))?;
+    if fs::read_dir(secret.path()).is_ok() {
+        // Permissions don't apply, e.g., when running as root
+        fs::set_permissions(secret.path(), fs::Permissions::from_mode(0o755))?;
+        return Ok(());
+    }
+
+    let run = |extra_args: &[&str]| -> Result<Vec<ProjectDto>> {
+        let output = Command::cargo_bin("makeclean")?
+            .args([
+                "--min-stale",
+                "0",
+                "--type",
+                "cargo",
+                "--no-cache",
+                "--json",
+            ])
+            .args(extra_args)
+            .current_dir(&root)
+            .output()?;
+        dbg!(String::from_utf8(output.stderr)?);
+        assert!(output.status.success());
+        Ok(String::from_utf8(output.stdout)?
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect())
+    };
+
+    let projects = run(&["--list"])?;
+    assert_eq!(projects.len(), 1);
+    assert_eq!(projects[0].errors.len(), 1);
+    assert!(projects[0].errors[0].contains("secret"));
+
+    run(&["--yes"])?;
+    assert!(project_dir.child("target").path().exists());
+
+    run(&["--yes", "--force"])?;
+    assert!(!project_dir.child("target").path().exists());
+
+    fs::set_permissions(secret.path(), fs::Permissions::from_mode(0o755))?;
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn with_trash_cleaned_directories_are_moved_to_the_trash() -> Result<()> {
+    let root = TempDir::new()?;
+    let data_home = TempDir::new()?;
+    let project_dir = root.child("project");
+    cargo_init(&project_dir)?;
+    cargo_build(&project_dir)?;
+
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--min-stale", "0", "--type", "cargo", "--yes", "--trash"])
+        .env("XDG_DATA_HOME", data_home.path())
+        .current_dir(&root)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)?.contains("pending until the trash is emptied"));
+
+    assert!(!project_dir.child("target").path().exists());
+    assert!(data_home.child("Trash/files/target").path().is_dir());
+    let info = std::fs::read_to_string(data_home.child("Trash/info/target.trashinfo").path())?;
+    assert!(info.contains("/project/target\n"));
+
+    Ok(())
+}
+
+#[test]
+fn directories_with_files_that_are_not_ignored_are_kept() -> Result<()> {
+    let root = TempDir::new()?;
+    let ignored_project = root.child("ignored");
+    cargo_init(&ignored_project)?;
+    cargo_build(&ignored_project)?;
+    git_init(&ignored_project, "/target\n", true);
+
+    // Everything in the target directory is committed
+    let tracked_project = root.child("tracked");
+    cargo_init(&tracked_project)?;
+    cargo_build(&tracked_project)?;
+    git_init(&tracked_project, "", true);
+
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--min-stale", "0", "--type", "cargo", "--yes"])
+        .current_dir(&root)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+
+    assert!(!ignored_project.child("target").path().exists());
+    assert!(tracked_project.child("target").path().is_dir());
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout.contains("1 directories not removed"));
+    assert!(stdout.contains("not ignored by Git"));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinked_directories_are_unlinked_but_their_targets_are_kept() -> Result<()> {
+    let root = TempDir::new()?;
+    let project_dir = root.child("project");
+    cargo_init(&project_dir)?;
+    cargo_build(&project_dir)?;
+    npm_init(&project_dir)?;
+    root.child("shared/node_modules/package/index.js")
+        .write_str("module.exports = {}")?;
+    std::os::unix::fs::symlink(
+        root.child("shared/node_modules").path(),
+        project_dir.child("node_modules").path(),
+    )?;
+
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--min-stale", "0", "--json", "--dry-run"])
+        .current_dir(&root)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    let project: ProjectDto = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(project.symlinked_ephemeral_dirs.len(), 1);
+    assert!(project.symlinked_ephemeral_dirs[0].ends_with("/project/node_modules"));
+
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--min-stale", "0", "--yes"])
+        .current_dir(&root)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)?.contains("is a symbolic link"));
+
+    assert!(!project_dir.child("target").path().exists());
+    assert!(std::fs::symlink_metadata(project_dir.child("node_modules").path()).is_err());
+    assert!(root
+        .child("shared/node_modules/package/index.js")
+        .path()
+        .is_file());
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn projects_in_use_are_only_cleaned_if_forced_to() -> Result<()> {
+    let root = TempDir::new()?;
+    let project_dir = root.child("project");
+    cargo_init(&project_dir)?;
+    cargo_build(&project_dir)?;
+
+    // Think of a development server running in the project directory
+    let mut process = Command::new("sleep")
+        .arg("60")
+        .current_dir(&project_dir)
+        .spawn()?;
+
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--min-stale", "0", "--yes"])
+        .current_dir(&root)
+        .output();
+    let forced_output = Command::cargo_bin("makeclean")?
+        .args(["--min-stale", "0", "--yes", "--force"])
+        .current_dir(&root)
+        .output();
+    process.kill()?;
+    process.wait()?;
+
+    let output = output?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout.contains("1 projects not cleaned, as they are in use"));
+    assert!(stdout.contains("sleep (pid"));
+
+    let forced_output = forced_output?;
+    dbg!(String::from_utf8(forced_output.stderr)?);
+    assert!(forced_output.status.success());
+    assert!(!project_dir.child("target").path().exists());
+
+    Ok(())
+}
+
+#[test]
+fn dependencies_that_might_not_be_restored_exactly_are_only_removed_if_forced_to() -> Result<()> {
+    let root = TempDir::new()?;
+    for project in ["locked", "unlocked"] {
+        let project_dir = root.child(project);
+        project_dir.child("package.json").write_str("{}")?;
+        project_dir
+            .child("node_modules/package/index.js")
+            .write_str("module.exports = {}")?;
+    }
+    root.child("locked/package-lock.json").write_str("{}")?;
+
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--min-stale", "0", "--list"])
+        .current_dir(&root)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout)?;
+    assert_eq!(stdout.matches("no lockfile").count(), 1);
+
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--min-stale", "0", "--yes"])
+        .current_dir(&root)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout
+        .contains("1 projects not cleaned, as their dependencies might not be restored exactly"));
+    assert!(!root.child("locked/node_modules").path().exists());
+    assert!(root.child("unlocked/node_modules").path().is_dir());
+
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--min-stale", "0", "--yes", "--force"])
+        .current_dir(&root)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    assert!(!root.child("unlocked/node_modules").path().exists());
+
+    Ok(())
+}
+
+#[test]
+fn only_the_selected_categories_are_cleaned() -> Result<()> {
+    let root = TempDir::new()?;
+    let project_dir = root.child("project");
+    cargo_init(&project_dir)?;
+    cargo_build(&project_dir)?;
+    project_dir.child("package.json").write_str("{}")?;
+    project_dir.child("package-lock.json").write_str("{}")?;
+    project_dir
+        .child("node_modules/package/index.js")
+        .write_str("module.exports = {}")?;
+
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--list", "--json"])
+        .current_dir(&root)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    let project: ProjectDto = serde_json::from_slice(&output.stdout)?;
+    let categories: Vec<String> = project
+        .category_bytes
+        .keys()
+        .map(|category| category.name().to_owned())
+        .collect();
+    assert_eq!(categories, vec!["build-outputs", "dependencies"]);
+
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--min-stale", "0", "--yes", "--only", "build-outputs"])
+        .current_dir(&root)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    assert!(!project_dir.child("target").path().exists());
+    assert!(project_dir.child("node_modules").path().is_dir());
+
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--min-stale", "0", "--yes", "--except", "build-outputs"])
+        .current_dir(&root)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    assert!(!project_dir.child("node_modules").path().exists());
+
+    Ok(())
+}
+
+#[test]
+fn the_paths_to_be_removed_are_reported_with_their_sizes() -> Result<()> {
+    let root = TempDir::new()?;
+    let project_dir = root.child("project");
+    cargo_init(&project_dir)?;
+    cargo_build(&project_dir)?;
+
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--list", "--json"])
+        .current_dir(&root)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    let project: ProjectDto = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(project.ephemeral_paths.len(), 1);
+    let target = &project.ephemeral_paths[0];
+    assert!(target.path.ends_with("project/target"));
+    assert_eq!(target.category, Some(Category::BuildOutputs));
+    assert_eq!(target.usage.freeable_bytes, project.freeable_bytes);
+
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--min-stale", "0", "--dry-run"])
+        .current_dir(&root)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout
+        .lines()
+        .any(|line| line.starts_with("rm -r '") && line.contains("project/target'  # ")));
+
+    Ok(())
+}
+
+#[test]
+fn a_dry_run_with_json_outputs_the_clean_plan() -> Result<()> {
+    let root = TempDir::new()?;
+    let project_dir = root.child("project");
+    cargo_init(&project_dir)?;
+    cargo_build(&project_dir)?;
+
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--min-stale", "0", "--json", "--dry-run", "--trash"])
+        .current_dir(&root)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    let project: ProjectDto = serde_json::from_slice(&output.stdout)?;
+    let plan = project.clean_plan.expect("has a plan");
+    assert_eq!(plan.len(), 1);
+    match &plan[0] {
+        CleanStep::Remove { path, usage } => {
+            assert!(path.ends_with("project/target"));
+            assert_eq!(usage.freeable_bytes, project.freeable_bytes);
+        }
+        step => panic!("unexpected step: {step:?}"),
+    }
+    assert!(project_dir.child("target").path().exists());
+
+    Ok(())
+}
+
+#[test]
+fn projects_pinned_with_the_dependencies_scope_only_lose_their_build_outputs() -> Result<()> {
+    let root = TempDir::new()?;
+    let project_dir = root.child("project");
+    project_dir.child("mix.exs").write_str("")?;
+    project_dir.child("mix.lock").write_str("")?;
+    project_dir.child("_build/dev/app.beam").write_str("beam")?;
+    project_dir.child("deps/dep/mix.exs").write_str("")?;
+    project_dir
+        .child(".makeclean-keep")
+        .write_str(r#"scope = "dependencies""#)?;
+
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--min-stale", "0", "--json", "--dry-run"])
+        .current_dir(&root)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    let project: ProjectDto = serde_json::from_slice(&output.stdout)?;
+    let build = &project.category_bytes[&Category::BuildOutputs];
+    assert_eq!(project.freeable_bytes, *build);
+
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--min-stale", "0", "--yes"])
+        .current_dir(&root)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    assert!(!project_dir.child("_build").path().exists());
+    assert!(project_dir.child("deps").path().exists());
+
+    Ok(())
+}
@@ -0,0 +1,227 @@
+use anyhow::Result;
+use assert_cmd::prelude::CommandCargoExt;
+use assert_fs::{
+    fixture::{FileWriteStr, PathChild},
+    TempDir,
+};
+use makeclean::project::dto::ProjectDto;
+use std::process::Command;
+
+use crate::util::{cargo::cargo_init, fs::canonicalized_str, npm::npm_init};
+
+#[test]
+fn local_config_file_sets_default_min_stale() -> Result<()> {
+    let root = TempDir::new()?;
+    let config_home = TempDir::new()?;
+    cargo_init(&root.child("project"))?;
+    root.child(".makeclean.toml")
+        .write_str(r#"min_stale = "1w""#)?;
+
+    // The project is new, so it's filtered out by the configured min_stale:
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--list", "--json"])
+        .env("XDG_CONFIG_HOME", config_home.path())
+        .current_dir(&root)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    let output = String::from_utf8(output.stdout)?;
+    assert!(
+        output.trim().is_empty(),
+        "Expected no output, got: {output:?}"
+    );
+
+    // Command-line arguments take precedence:
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--list", "--json", "--min-stale", "0"])
+        .env("XDG_CONFIG_HOME", config_home.path())
+        .current_dir(&root)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    let output = String::from_utf8(output.stdout)?;
+    let project: ProjectDto = serde_json::from_str(output.trim()).unwrap();
+    assert_eq!(project.path, canonicalized_str(root.child("project")));
+
+    Ok(())
+}
+
+#[test]
+fn excluded_directories_and_disabled_tools_are_not_considered() -> Result<()> {
+    let root = TempDir::new()?;
+    let config_home = TempDir::new()?;
+    cargo_init(&root.child("project"))?;
+    cargo_init(&root.child("vendor").child("dependency"))?;
+    npm_init(&root.child("frontend"))?;
+    config_home
+        .child("makeclean")
+        .child("config.toml")
+        .write_str(
+            r#"
+        exclude = ["vendor"]
+
+        [tools.npm]
+        enabled = false
+        "#,
+        )?;
+
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--list", "--json"])
+        .env("XDG_CONFIG_HOME", config_home.path())
+        .current_dir(&root)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    let output = String::from_utf8(output.stdout)?;
+
+    // Only the Cargo project outside of `vendor` is found:
+    let project: ProjectDto = serde_json::from_str(output.trim()).unwrap();
+    assert_eq!(project.path, canonicalized_str(root.child("project")));
+
+    Ok(())
+}
+
+#[test]
+fn prints_the_effective_configuration() -> Result<()> {
+    let root = TempDir::new()?;
+    let config_home = TempDir::new()?;
+    config_home
+        .child("makeclean")
+        .child("config.toml")
+        .write_str("min_stale = \"2w\"\ntypes = [\"npm\"]\n")?;
+    root.child(".makeclean.toml")
+        .write_str("min_stale = \"1y\"\n")?;
+
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--print-config", "--type", "cargo"])
+        .env("XDG_CONFIG_HOME", config_home.path())
+        .current_dir(&root)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    let output = String::from_utf8(output.stdout)?;
+
+    let config: makeclean::config::Config = toml::from_str(&output)?;
+    assert_eq!(config.min_stale.as_deref(), Some("1y"));
+    assert_eq!(config.types, vec!["cargo"]);
+
+    Ok(())
+}
+
+#[test]
+fn the_local_config_file_is_looked_up_from_the_directory_to_search() -> Result<()> {
+    let root = TempDir::new()?;
+    let elsewhere = TempDir::new()?;
+    let config_home = TempDir::new()?;
+    root.child(".makeclean.toml")
+        .write_str("hidden = true\narchive.enabled = true\n")?;
+
+    let output = Command::cargo_bin("makeclean")?
+        .arg("--print-config")
+        .arg(root.path())
+        .env("XDG_CONFIG_HOME", config_home.path())
+        .current_dir(&elsewhere)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    let config: makeclean::config::Config = toml::from_str(&String::from_utf8(output.stdout)?)?;
+    assert_eq!(config.hidden, Some(true));
+    assert_eq!(config.archive.enabled, Some(true));
+
+    // Flags set in the configuration can be turned off on the command line:
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--print-config", "--no-hidden", "--no-archive"])
+        .arg(root.path())
+        .env("XDG_CONFIG_HOME", config_home.path())
+        .current_dir(&elsewhere)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    let config: makeclean::config::Config = toml::from_str(&String::from_utf8(output.stdout)?)?;
+    assert_eq!(config.hidden, Some(false));
+    assert_eq!(config.archive.enabled, Some(false));
+
+    Ok(())
+}
+
+#[test]
+fn invalid_configuration_is_reported() -> Result<()> {
+    let root = TempDir::new()?;
+    let config_home = TempDir::new()?;
+    root.child(".makeclean.toml")
+        .write_str("types = [\"cobol\"]\n")?;
+
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--list"])
+        .env("XDG_CONFIG_HOME", config_home.path())
+        .current_dir(&root)
+        .output()?;
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?.contains("cobol"));
+
+    Ok(())
+}
+
+#[test]
+fn custom_tools_are_recognized_and_selectable_by_type() -> Result<()> {
+    let root = TempDir::new()?;
+    let config_home = TempDir::new()?;
+    cargo_init(&root.child("cargo-project"))?;
+    root.child("bazel-project")
+        .child("WORKSPACE")
+        .write_str("workspace(name = \"the_workspace\")")?;
+    config_home
+        .child("makeclean")
+        .child("config.toml")
+        .write_str(
+            r#"
+        [[custom_tools]]
+        name = "bazel"
+        markers = ["WORKSPACE"]
+        ephemeral_dirs = ["bazel-*"]
+        project_name = { file = "WORKSPACE", regex = 'name = "(\w+)"' }
+        "#,
+        )?;
+
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--list", "--json", "--type", "bazel"])
+        .env("XDG_CONFIG_HOME", config_home.path())
+        .current_dir(&root)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    let output = String::from_utf8(output.stdout)?;
+
+    // Only the Bazel project is found:
+    let project: ProjectDto = serde_json::from_str(output.trim()).unwrap();
+    assert_eq!(project.path, canonicalized_str(root.child("bazel-project")));
+    assert_eq!(project.name, "the_workspace");
+    assert_eq!(project.build_tools, vec!["bazel"]);
+
+    Ok(())
+}
+
+#[test]
+fn the_help_text_lists_custom_tools() -> Result<()> {
+    let config = TempDir::new()?;
+    config.child("config.toml").write_str(
+        r#"
+        [[custom_tools]]
+        name = "bazel"
+        aliases = ["blaze"]
+        markers = ["WORKSPACE"]
+        "#,
+    )?;
+
+    let output = Command::cargo_bin("makeclean")?
+        .arg("--config")
+        .arg(config.child("config.toml").path())
+        .arg("--help")
+        .output()?;
+    assert!(output.status.success());
+    let output = String::from_utf8(output.stdout)?;
+    assert!(output.contains("npm, bazel"));
+
+    Ok(())
+}
@@ -251,3 +251,163 @@ fn accepts_multiple_directories_as_input_and_deduplicates_by_path() -> Result<()
 
     Ok(())
 }
+
+#[test]
+fn lists_project_types_including_custom_tools() -> Result<()> {
+    use assert_fs::fixture::FileWriteStr;
+    use makeclean::project::dto::BuildToolProbeDto;
+
+    let root = TempDir::new()?;
+    root.child(".makeclean.toml").write_str(
+        r#"
+        [[custom_tools]]
+        name = "bazel"
+        aliases = ["blaze"]
+        markers = ["WORKSPACE"]
+        ephemeral_dirs = ["bazel-*"]
+        "#,
+    )?;
+
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--list-types", "--json"])
+        .current_dir(&root)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    let output = String::from_utf8(output.stdout)?;
+
+    let types: Vec<BuildToolProbeDto> = output
+        .trim()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let cargo = types.iter().find(|t| t.id == "cargo").unwrap();
+    assert_eq!(cargo.aliases, vec!["rust", "rs"]);
+    assert_eq!(cargo.ephemeral_dirs, vec!["target"]);
+    let bazel = types.iter().find(|t| t.id == "bazel").unwrap();
+    assert_eq!(bazel.aliases, vec!["blaze"]);
+    assert_eq!(bazel.ephemeral_dirs, vec!["bazel-*"]);
+
+    Ok(())
+}
+
+#[test]
+fn rejects_unknown_project_types() -> Result<()> {
+    let root = TempDir::new()?;
+
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--list", "--type", "cobol"])
+        .current_dir(&root)
+        .output()?;
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?.contains("Unknown project type \"cobol\""));
+
+    Ok(())
+}
+
+#[test]
+fn excluded_directories_and_directories_below_max_depth_are_not_considered() -> Result<()> {
+    let root = TempDir::new()?;
+    let project_dir = root.child("project");
+    cargo_init(&project_dir)?;
+    cargo_init(&root.child("vendor").child("dependency"))?;
+    cargo_init(&root.child("deeply").child("nested").child("project"))?;
+
+    let output = Command::cargo_bin("makeclean")?
+        .args([
+            "--list",
+            "--json",
+            "--exclude",
+            "vendor",
+            "--max-depth",
+            "2",
+        ])
+        .current_dir(&root)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    let output = String::from_utf8(output.stdout)?;
+
+    // We expect a single line/project
+    let project: ProjectDto = serde_json::from_str(output.trim()).unwrap();
+    assert_eq!(project.path, canonicalized_str(&project_dir));
+
+    Ok(())
+}
+
+#[test]
+fn unchanged_projects_are_listed_from_the_scan_index() -> Result<()> {
+    use assert_fs::fixture::FileWriteStr;
+
+    let root = TempDir::new()?;
+    let cache_home = TempDir::new()?;
+    let project_dir = root.child("project");
+    cargo_init(&project_dir)?;
+
+    let list = |extra_args: &[&str]| -> Result<ProjectDto> {
+        let output = Command::cargo_bin("makeclean")?
+            .args(["--list", "--json"])
+            .args(extra_args)
+            .env("XDG_CACHE_HOME", cache_home.path())
+            .current_dir(&root)
+            .output()?;
+        dbg!(String::from_utf8(output.stderr)?);
+        assert!(output.status.success());
+        let output = String::from_utf8(output.stdout)?;
+        Ok(serde_json::from_str(output.trim())?)
+    };
+
+    assert_eq!(list(&[])?.name, "cargo_test_project");
+    assert!(cache_home.child("makeclean/index.json").exists());
+
+    // Modifying a file in place doesn't change any directory, so the index
+    // entry is still considered valid:
+    project_dir
+        .child("Cargo.toml")
+        .write_str("[package]\nname = \"renamed\"\nversion = \"0.1.0\"\n")?;
+    assert_eq!(list(&[])?.name, "cargo_test_project");
+
+    // ..unless the index is bypassed:
+    assert_eq!(list(&["--no-cache"])?.name, "renamed");
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn hard_linked_dependencies_are_only_freeable_if_no_links_remain() -> Result<()> {
+    use assert_fs::fixture::{FileWriteStr, PathCreateDir};
+
+    let root = TempDir::new()?;
+    let store = root.child("store");
+    store.child("package.js").write_str(&"x".repeat(10_000))?;
+    for project in ["a", "b"] {
+        let project_dir = root.child(project);
+        project_dir.child("package.json").write_str("{}")?;
+        project_dir.child("node_modules").create_dir_all()?;
+        std::fs::hard_link(
+            store.child("package.js").path(),
+            project_dir.child("node_modules/package.js").path(),
+        )?;
+    }
+
+    let output = Command::cargo_bin("makeclean")?
+        .args(["--list", "--json", "--no-cache"])
+        .current_dir(&root)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    let output = String::from_utf8(output.stdout)?;
+    let projects: Vec<ProjectDto> = output
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    assert_eq!(projects.len(), 2);
+    assert!(projects.iter().all(|p| p.apparent_bytes == 10_000));
+    // The store keeps the file, so cleaning either of them frees nothing:
+    assert!(projects.iter().all(|p| p.freeable_bytes == 0));
+
+    Ok(())
+}
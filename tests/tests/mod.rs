@@ -1,4 +1,6 @@
 pub mod archive;
 pub mod build_tools;
 pub mod clean;
+pub mod config;
 pub mod list;
+pub mod undo;
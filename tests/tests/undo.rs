@@ -0,0 +1,125 @@
+use anyhow::Result;
+use assert_cmd::prelude::CommandCargoExt;
+use assert_fs::{fixture::PathChild, TempDir};
+use std::{
+    path::Path,
+    process::{Command, Output},
+};
+
+use crate::util::cargo::{cargo_build, cargo_init};
+
+#[cfg(unix)]
+#[test]
+fn undo_restores_trashed_directories_and_archived_projects() -> Result<()> {
+    let root = TempDir::new()?;
+    let data_home = TempDir::new()?;
+    let state_home = TempDir::new()?;
+    let project_dir = root.child("project");
+    cargo_init(&project_dir)?;
+    cargo_build(&project_dir)?;
+
+    let output = Command::cargo_bin("makeclean")?
+        .args([
+            "--min-stale",
+            "0",
+            "--type",
+            "cargo",
+            "--yes",
+            "--trash",
+            "--archive",
+        ])
+        .env("XDG_DATA_HOME", data_home.path())
+        .env("XDG_STATE_HOME", state_home.path())
+        .current_dir(&root)
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)?.contains("makeclean undo "));
+    assert!(!project_dir.child("Cargo.toml").path().exists());
+    assert!(!project_dir.child("target").path().exists());
+
+    let journal = std::fs::read_to_string(state_home.child("makeclean/journal.jsonl").path())?;
+    assert_eq!(journal.lines().count(), 2);
+
+    let output = Command::cargo_bin("makeclean")?
+        .args(["undo", "--last"])
+        .env("XDG_STATE_HOME", state_home.path())
+        .output()?;
+    dbg!(String::from_utf8(output.stderr)?);
+    assert!(output.status.success());
+
+    assert!(project_dir.child("Cargo.toml").path().is_file());
+    assert!(project_dir.child("target").path().is_dir());
+    assert!(!project_dir
+        .child("cargo_test_project.tar.xz")
+        .path()
+        .exists());
+    assert!(!data_home.child("Trash/files/target").path().exists());
+    assert!(!data_home
+        .child("Trash/info/target.trashinfo")
+        .path()
+        .exists());
+
+    // Nothing is left to undo
+    let output = Command::cargo_bin("makeclean")?
+        .arg("undo")
+        .env("XDG_STATE_HOME", state_home.path())
+        .output()?;
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)?.contains("Nothing to undo"));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn staged_directories_are_restored_or_deleted_after_the_grace_period() -> Result<()> {
+    let root = TempDir::new()?;
+    let data_home = TempDir::new()?;
+    let state_home = TempDir::new()?;
+    let project_dir = root.child("project");
+    cargo_init(&project_dir)?;
+    cargo_build(&project_dir)?;
+
+    let makeclean = |args: &[&str], current_dir: &Path| -> Result<Output> {
+        let output = Command::cargo_bin("makeclean")?
+            .args(args)
+            .env("XDG_DATA_HOME", data_home.path())
+            .env("XDG_STATE_HOME", state_home.path())
+            .current_dir(current_dir)
+            .output()?;
+        dbg!(String::from_utf8_lossy(&output.stderr));
+        assert!(output.status.success());
+        Ok(output)
+    };
+    let clean_args = ["--min-stale", "0", "--type", "cargo", "--yes", "--stage"];
+
+    makeclean(&clean_args, root.path())?;
+    assert!(!project_dir.child("target").path().exists());
+    assert!(data_home.child("makeclean/staged/target~1").path().is_dir());
+
+    makeclean(&["restore"], project_dir.path())?;
+    assert!(project_dir.child("target").path().is_dir());
+    assert!(!data_home.child("makeclean/staged/target~1").path().exists());
+
+    // Staged directories are kept until the grace period they were staged
+    // with is over, and only deleted by runs that stage
+    let mut short_grace_period = clean_args.to_vec();
+    short_grace_period.extend(["--grace-period", "0"]);
+    makeclean(&short_grace_period, root.path())?;
+    assert!(data_home.child("makeclean/staged/target~1").path().is_dir());
+    makeclean(&["--min-stale", "0", "--yes"], root.path())?;
+    assert!(data_home.child("makeclean/staged/target~1").path().is_dir());
+
+    cargo_build(&project_dir)?;
+    let output = makeclean(&clean_args, root.path())?;
+    assert!(String::from_utf8(output.stdout)?.contains("grace period is over"));
+    assert!(!data_home.child("makeclean/staged/target~1").path().exists());
+    assert!(data_home.child("makeclean/staged/target~2").path().is_dir());
+
+    // A shorter grace period doesn't affect what was staged before
+    makeclean(&short_grace_period, root.path())?;
+    assert!(data_home.child("makeclean/staged/target~2").path().is_dir());
+
+    Ok(())
+}